@@ -0,0 +1,680 @@
+use std::collections::HashMap;
+
+use crate::cpu::{CHIP8_PROGRAM_START, XOCHIP_RAM_SIZE};
+use crate::error::AssembleError;
+
+// An assembler for Octo (https://github.com/JohnEarnest/Octo) source files.
+//
+// Supported: labels (`: name`), `:const`, `:alias`, `:macro`, `:org`,
+// `:byte`, `:call`, bare numbers as data bytes, every instruction the CPU
+// executes, `if ... then`, `if ... begin ... else ... end` and
+// `loop ... while ... again`. Not supported: `:calc`, `:next`, `:unpack`,
+// `:stringmode`, the `<`/`>` comparison pseudo-ops and `scroll-up`.
+//
+// As in Octo, a program that defines `main` starts with a jump to it.
+
+// Guards against macros that expand themselves forever.
+const MAX_MACRO_DEPTH: usize = 32;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// An address operand that refers to a label defined further down.
+struct Fixup {
+    // Where the operand is stored.
+    address: usize,
+    // 12-bit operand of a regular instruction, or 16-bit operand of `i := long`.
+    long: bool,
+    label: Token,
+}
+
+// Open control flow blocks.
+enum Block {
+    // `begin` or `else`, with the jump that skips over the block.
+    If { jump: usize, token: Token },
+    // `loop`, with the jumps out of it emitted by `while`.
+    Loop { start: usize, exits: Vec<usize>, token: Token },
+}
+
+// Assembles a program that is loaded at the usual 0x200.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    assemble_at(source, CHIP8_PROGRAM_START)
+}
+
+// Assembles a program that is loaded at `origin`.
+pub fn assemble_at(source: &str, origin: usize) -> Result<Vec<u8>, AssembleError> {
+    let mut tokens = Vec::new();
+    expand(&tokenize(source), &mut HashMap::new(), &mut tokens, 0)?;
+    let mut assembler = Assembler {
+        tokens,
+        pos: 0,
+        origin,
+        address: origin,
+        rom: Vec::new(),
+        emitted: Vec::new(),
+        statement: 0,
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+    assembler.run()?;
+    Ok(assembler.rom)
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let mut current: Option<Token> = None;
+        for (column, c) in line.chars().enumerate() {
+            if c.is_whitespace() {
+                tokens.extend(current.take());
+            } else {
+                current
+                    .get_or_insert_with(|| Token { text: String::new(), line: index + 1, column: column + 1 })
+                    .text
+                    .push(c);
+            }
+        }
+        tokens.extend(current);
+    }
+    tokens
+}
+
+fn error<T>(token: &Token, message: String) -> Result<T, AssembleError> {
+    Err(AssembleError { line: token.line, column: token.column, message })
+}
+
+// Collects macro definitions and replaces macro invocations with their bodies.
+fn expand(
+    tokens: &[Token],
+    macros: &mut HashMap<String, Macro>,
+    out: &mut Vec<Token>,
+    depth: usize,
+) -> Result<(), AssembleError> {
+    let mut pos = 0;
+    while pos < tokens.len() {
+        let token = &tokens[pos];
+        pos += 1;
+        if token.text == ":macro" {
+            let name = match tokens.get(pos) {
+                Some(name) => name.text.clone(),
+                None => return error(token, "expected a macro name".to_string()),
+            };
+            pos += 1;
+            let mut params = Vec::new();
+            loop {
+                match tokens.get(pos) {
+                    Some(param) if param.text == "{" => break,
+                    Some(param) => params.push(param.text.clone()),
+                    None => return error(token, format!("macro '{}' has no body", name)),
+                }
+                pos += 1;
+            }
+            pos += 1;
+            let mut body = Vec::new();
+            let mut nesting = 0;
+            loop {
+                match tokens.get(pos) {
+                    Some(t) if t.text == "}" && nesting == 0 => break,
+                    Some(t) => {
+                        if t.text == "{" {
+                            nesting += 1;
+                        } else if t.text == "}" {
+                            nesting -= 1;
+                        }
+                        body.push(t.clone());
+                    }
+                    None => return error(token, format!("macro '{}' is not closed", name)),
+                }
+                pos += 1;
+            }
+            pos += 1;
+            macros.insert(name, Macro { params, body });
+        } else if let Some(definition) = macros.get(&token.text).cloned() {
+            if depth == MAX_MACRO_DEPTH {
+                return error(token, format!("macro '{}' is nested too deeply", token.text));
+            }
+            if pos + definition.params.len() > tokens.len() {
+                return error(token, format!(
+                    "macro '{}' takes {} arguments", token.text, definition.params.len()
+                ));
+            }
+            let args = &tokens[pos..pos + definition.params.len()];
+            pos += args.len();
+            let body: Vec<Token> = definition.body.iter()
+                .map(|t| match definition.params.iter().position(|param| *param == t.text) {
+                    Some(index) => args[index].clone(),
+                    None => t.clone(),
+                })
+                .collect();
+            expand(&body, macros, out, depth + 1)?;
+        } else {
+            out.push(token.clone());
+        }
+    }
+    Ok(())
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<usize> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+            digit.to_digit(16).map(|digit| digit as usize)
+        }
+        _ => None,
+    }
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    pos: usize,
+    origin: usize,
+    // Where the next byte goes.
+    address: usize,
+    rom: Vec<u8>,
+    // Which bytes of `rom` were emitted, as opposed to skipped by `:org`.
+    emitted: Vec<bool>,
+    // The first token of the statement being assembled.
+    statement: usize,
+    labels: HashMap<String, usize>,
+    consts: HashMap<String, i64>,
+    aliases: HashMap<String, usize>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+impl Assembler {
+
+    fn run(&mut self) -> Result<(), AssembleError> {
+        let main = self.tokens.windows(2)
+            .find(|pair| pair[0].text == ":" && pair[1].text == "main")
+            .map(|pair| pair[1].clone());
+        if let Some(main) = main {
+            self.fixups.push(Fixup { address: self.address, long: false, label: main });
+            self.emit(0x1000)?;
+        }
+
+        while self.pos < self.tokens.len() {
+            self.statement()?;
+        }
+
+        if let Some(block) = self.blocks.pop() {
+            let (Block::If { token, .. } | Block::Loop { token, .. }) = block;
+            return error(&token, format!("'{}' is never closed", token.text));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let target = match self.labels.get(&fixup.label.text) {
+                Some(&target) => target,
+                None => return error(&fixup.label, format!("undefined label '{}'", fixup.label.text)),
+            };
+            let offset = fixup.address - self.origin;
+            if fixup.long {
+                self.rom[offset] = (target >> 8) as u8;
+                self.rom[offset + 1] = target as u8;
+            } else if target > 0xFFF {
+                return error(&fixup.label, format!(
+                    "label '{}' at {:#X} is out of reach, use `i := long`", fixup.label.text, target
+                ));
+            } else {
+                self.rom[offset] = (self.rom[offset] & 0xF0) | (target >> 8) as u8;
+                self.rom[offset + 1] = target as u8;
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        self.statement = self.pos;
+        let token = self.next()?;
+        match token.text.as_str() {
+            ":" => {
+                let name = self.identifier()?;
+                if self.labels.insert(name.text.clone(), self.address).is_some() {
+                    return error(&name, format!("label '{}' is already defined", name.text));
+                }
+            }
+            ":const" => {
+                let name = self.identifier()?;
+                let value = self.next()?;
+                let value = self.number(&value)?;
+                self.consts.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.identifier()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":org" => {
+                let value = self.next()?;
+                let address = self.number(&value)?;
+                if address < self.origin as i64 || address >= XOCHIP_RAM_SIZE as i64 {
+                    return error(&value, format!("cannot place code at {:#X}", address));
+                }
+                self.address = address as usize;
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_byte(byte)?;
+            }
+            ":call" => {
+                let address = self.address_operand(false)?;
+                self.emit(0x2000 | address)?;
+            }
+            "clear" => self.emit(0x00E0)?,
+            "return" | ";" => self.emit(0x00EE)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)?;
+            }
+            "scroll-right" => self.emit(0x00FB)?,
+            "scroll-left" => self.emit(0x00FC)?,
+            "exit" => self.emit(0x00FD)?,
+            "lores" => self.emit(0x00FE)?,
+            "hires" => self.emit(0x00FF)?,
+            "jump" => {
+                let address = self.address_operand(false)?;
+                self.emit(0x1000 | address)?;
+            }
+            "jump0" => {
+                let address = self.address_operand(false)?;
+                self.emit(0xB000 | address)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n)?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit_x(0xF033, x)?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let store = token.text == "save";
+                if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let base = if store { 0x5002 } else { 0x5003 };
+                    self.emit(base | (x as u16) << 8 | (y as u16) << 4)?;
+                } else {
+                    self.emit_x(if store { 0xF055 } else { 0xF065 }, x)?;
+                }
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit_x(0xF075, x)?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit_x(0xF085, x)?;
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8)?;
+            }
+            "audio" => self.emit(0xF002)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let base = match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit_x(base, x)?;
+            }
+            "i" => self.index_statement()?,
+            "if" => {
+                let (skip_unless, skip_if) = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text.as_str() {
+                    "then" => self.emit(skip_unless)?,
+                    "begin" => {
+                        self.emit(skip_if)?;
+                        let jump = self.address;
+                        self.emit(0x1000)?;
+                        self.blocks.push(Block::If { jump, token });
+                    }
+                    _ => return error(&keyword, format!("expected 'then' or 'begin', found '{}'", keyword.text)),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let else_jump = self.address;
+                    self.emit(0x1000)?;
+                    self.patch_jump(jump, self.address, &token)?;
+                    self.blocks.push(Block::If { jump: else_jump, token });
+                }
+                _ => return error(&token, "'else' without 'begin'".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => self.patch_jump(jump, self.address, &token)?,
+                _ => return error(&token, "'end' without 'begin'".to_string()),
+            },
+            "loop" => self.blocks.push(Block::Loop { start: self.address, exits: Vec::new(), token }),
+            "while" => {
+                let (_, skip_if) = self.condition()?;
+                let exit = self.address + 2;
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    Some(Block::Loop { exits, .. }) => exits.push(exit),
+                    _ => return error(&token, "'while' outside of a loop".to_string()),
+                }
+                self.emit(skip_if)?;
+                self.emit(0x1000)?;
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    let jump = self.address;
+                    self.emit(0x1000)?;
+                    self.patch_jump(jump, start, &token)?;
+                    for exit in exits {
+                        self.patch_jump(exit, self.address, &token)?;
+                    }
+                }
+                _ => return error(&token, "'again' without 'loop'".to_string()),
+            },
+            _ => {
+                if let Some(value) = parse_number(&token.text) {
+                    let byte = self.check_byte(&token, value)?;
+                    self.emit_byte(byte)?;
+                } else if self.is_register(&token.text) {
+                    self.pos -= 1;
+                    self.register_statement()?;
+                } else if token.text.starts_with(':') {
+                    return error(&token, format!("unsupported directive '{}'", token.text));
+                } else {
+                    // A bare name calls the subroutine at that label.
+                    self.pos -= 1;
+                    let address = self.address_operand(false)?;
+                    self.emit(0x2000 | address)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // `i := addr`, `i := long addr`, `i := hex vx`, `i := bighex vx`, `i += vx`.
+    fn index_statement(&mut self) -> Result<(), AssembleError> {
+        let operator = self.next()?;
+        match operator.text.as_str() {
+            ":=" => {
+                if self.peek_is("hex") {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.emit_x(0xF029, x)
+                } else if self.peek_is("bighex") {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.emit_x(0xF030, x)
+                } else if self.peek_is("long") {
+                    self.next()?;
+                    self.emit(0xF000)?;
+                    let address = self.address_operand(true)?;
+                    self.emit(address)
+                } else {
+                    let address = self.address_operand(false)?;
+                    self.emit(0xA000 | address)
+                }
+            }
+            "+=" => {
+                let x = self.register()?;
+                self.emit_x(0xF01E, x)
+            }
+            _ => error(&operator, format!("expected ':=' or '+=', found '{}'", operator.text)),
+        }
+    }
+
+    // Assignments and arithmetic on a V register.
+    fn register_statement(&mut self) -> Result<(), AssembleError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+        let xy = |base: u16, y: usize| base | (x as u16) << 8 | (y as u16) << 4;
+        match operator.text.as_str() {
+            ":=" => {
+                if self.peek_is("random") {
+                    self.next()?;
+                    let kk = self.byte()?;
+                    self.emit_x(0xC000 | kk as u16, x)
+                } else if self.peek_is("delay") {
+                    self.next()?;
+                    self.emit_x(0xF007, x)
+                } else if self.peek_is("key") {
+                    self.next()?;
+                    self.emit_x(0xF00A, x)
+                } else if self.peek_register() {
+                    let y = self.register()?;
+                    self.emit(xy(0x8000, y))
+                } else {
+                    let kk = self.byte()?;
+                    self.emit_x(0x6000 | kk as u16, x)
+                }
+            }
+            "+=" | "-=" => {
+                let subtract = operator.text == "-=";
+                if self.peek_register() {
+                    let y = self.register()?;
+                    self.emit(xy(if subtract { 0x8005 } else { 0x8004 }, y))
+                } else {
+                    let kk = self.byte()?;
+                    let kk = if subtract { kk.wrapping_neg() } else { kk };
+                    self.emit_x(0x7000 | kk as u16, x)
+                }
+            }
+            "=-" | "|=" | "&=" | "^=" | ">>=" | "<<=" => {
+                let y = self.register()?;
+                let base = match operator.text.as_str() {
+                    "=-" => 0x8007,
+                    "|=" => 0x8001,
+                    "&=" => 0x8002,
+                    "^=" => 0x8003,
+                    ">>=" => 0x8006,
+                    _ => 0x800E,
+                };
+                self.emit(xy(base, y))
+            }
+            _ => error(&operator, format!("unknown operator '{}'", operator.text)),
+        }
+    }
+
+    // Parses `vx == n`, `vx != vy`, `vx key` and so on. Returns the instruction
+    // that skips when the condition is false, and the one that skips when it holds.
+    fn condition(&mut self) -> Result<(u16, u16), AssembleError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+        let with_x = |opcode: u16| opcode | (x as u16) << 8;
+        let (skip_unless, skip_if) = match operator.text.as_str() {
+            "key" => (with_x(0xE0A1), with_x(0xE09E)),
+            "-key" => (with_x(0xE09E), with_x(0xE0A1)),
+            "==" | "!=" => {
+                let (equal, not_equal) = if self.peek_register() {
+                    let y = (self.register()? as u16) << 4;
+                    (with_x(0x5000 | y), with_x(0x9000 | y))
+                } else {
+                    let kk = self.byte()? as u16;
+                    (with_x(0x3000 | kk), with_x(0x4000 | kk))
+                };
+                if operator.text == "==" { (not_equal, equal) } else { (equal, not_equal) }
+            }
+            _ => return error(&operator, format!("unsupported comparison '{}'", operator.text)),
+        };
+        Ok((skip_unless, skip_if))
+    }
+
+    fn next(&mut self) -> Result<Token, AssembleError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => {
+                let (line, column) = self.tokens.last()
+                    .map(|last| (last.line, last.column + last.text.chars().count()))
+                    .unwrap_or((1, 1));
+                Err(AssembleError { line, column, message: "unexpected end of input".to_string() })
+            }
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.get(self.pos).is_some_and(|token| token.text == text)
+    }
+
+    fn peek_register(&self) -> bool {
+        self.tokens.get(self.pos).is_some_and(|token| self.is_register(&token.text))
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return error(&token, format!("expected '{}', found '{}'", text, token.text));
+        }
+        Ok(())
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        parse_register(text).is_some() || self.aliases.contains_key(text)
+    }
+
+    fn register(&mut self) -> Result<usize, AssembleError> {
+        let token = self.next()?;
+        match parse_register(&token.text).or_else(|| self.aliases.get(&token.text).cloned()) {
+            Some(register) => Ok(register),
+            None => error(&token, format!("expected a register, found '{}'", token.text)),
+        }
+    }
+
+    // A name for a label, constant or alias.
+    fn identifier(&mut self) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        if parse_number(&token.text).is_some() || parse_register(&token.text).is_some() {
+            return error(&token, format!("'{}' cannot be used as a name", token.text));
+        }
+        Ok(token)
+    }
+
+    fn number(&self, token: &Token) -> Result<i64, AssembleError> {
+        match parse_number(&token.text).or_else(|| self.consts.get(&token.text).cloned()) {
+            Some(value) => Ok(value),
+            None => error(token, format!("expected a number, found '{}'", token.text)),
+        }
+    }
+
+    fn check_byte(&self, token: &Token, value: i64) -> Result<u8, AssembleError> {
+        if !(-128..=255).contains(&value) {
+            return error(token, format!("{} does not fit in a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn byte(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        let value = self.number(&token)?;
+        self.check_byte(&token, value)
+    }
+
+    fn nibble(&mut self) -> Result<u16, AssembleError> {
+        let token = self.next()?;
+        match self.number(&token)? {
+            value @ 0..=15 => Ok(value as u16),
+            value => error(&token, format!("{} does not fit in a nibble", value)),
+        }
+    }
+
+    // A number, constant or label used as an address. Labels that are not
+    // defined yet are filled in at the end.
+    fn address_operand(&mut self, long: bool) -> Result<u16, AssembleError> {
+        let token = self.next()?;
+        let max = if long { 0xFFFF } else { 0xFFF };
+        let value = match parse_number(&token.text).or_else(|| self.consts.get(&token.text).cloned()) {
+            Some(value) => value,
+            None => match self.labels.get(&token.text) {
+                Some(&address) => address as i64,
+                None => {
+                    self.fixups.push(Fixup { address: self.address, long, label: token });
+                    return Ok(0);
+                }
+            },
+        };
+        if value < 0 || value > max {
+            return error(&token, format!("address {:#X} is out of range", value));
+        }
+        Ok(value as u16)
+    }
+
+    // Points the jump at `address`, emitted for the control flow `token`, to `target`.
+    fn patch_jump(&mut self, address: usize, target: usize, token: &Token) -> Result<(), AssembleError> {
+        if target > 0xFFF {
+            return error(token, format!("'{}' jumps to {:#X}, which is out of reach", token.text, target));
+        }
+        let offset = address - self.origin;
+        self.rom[offset] = 0x10 | (target >> 8) as u8;
+        self.rom[offset + 1] = target as u8;
+        Ok(())
+    }
+
+    fn emit_x(&mut self, base: u16, x: usize) -> Result<(), AssembleError> {
+        self.emit(base | (x as u16) << 8)
+    }
+
+    fn emit(&mut self, word: u16) -> Result<(), AssembleError> {
+        self.emit_byte((word >> 8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AssembleError> {
+        if self.address >= XOCHIP_RAM_SIZE {
+            let token = &self.tokens[self.pos.saturating_sub(1)];
+            return error(token, "program does not fit in memory".to_string());
+        }
+        let offset = self.address - self.origin;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+            self.emitted.resize(offset + 1, false);
+        }
+        if self.emitted[offset] {
+            let token = &self.tokens[self.statement];
+            return error(token, format!("code at {:#X} overlaps earlier code", self.address));
+        }
+        self.rom[offset] = byte;
+        self.emitted[offset] = true;
+        self.address += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "./assembler_tests.rs"]
+mod assembler_tests;
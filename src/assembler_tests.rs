@@ -0,0 +1,215 @@
+use crate::assembler::*;
+use crate::error::AssembleError;
+use crate::instruction::{decode, disassemble, Instruction};
+
+// Octo source for an instruction. The match is exhaustive on purpose, so that
+// new instructions have to be taught to the assembler too.
+fn octo(instruction: Instruction) -> Option<String> {
+    Some(match instruction {
+        Instruction::Scd(n) => format!("scroll-down {}", n),
+        Instruction::Cls => "clear".to_string(),
+        Instruction::Ret => "return".to_string(),
+        Instruction::Scr => "scroll-right".to_string(),
+        Instruction::Scl => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::Low => "lores".to_string(),
+        Instruction::High => "hires".to_string(),
+        Instruction::Jp(nnn) => format!("jump {}", nnn),
+        Instruction::Call(nnn) => format!(":call {}", nnn),
+        Instruction::SeByte { x, kk } => format!("if v{:x} != {} then", x, kk),
+        Instruction::SneByte { x, kk } => format!("if v{:x} == {} then", x, kk),
+        Instruction::SeReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+        Instruction::LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        Instruction::LdByte { x, kk } => format!("v{:x} := {}", x, kk),
+        Instruction::AddByte { x, kk } => format!("v{:x} += {}", x, kk),
+        Instruction::LdReg { x, y } => format!("v{:x} := v{:x}", x, y),
+        Instruction::Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        Instruction::And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Instruction::Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::AddReg { x, y } => format!("v{:x} += v{:x}", x, y),
+        Instruction::Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+        Instruction::Shr { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::Subn { x, y } => format!("v{:x} =- v{:x}", x, y),
+        Instruction::Shl { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::SneReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::LdI(nnn) => format!("i := {}", nnn),
+        Instruction::JpV0(nnn) => format!("jump0 {}", nnn),
+        Instruction::Rnd { x, kk } => format!("v{:x} := random {}", x, kk),
+        Instruction::Drw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Instruction::Skp(x) => format!("if v{:x} -key then", x),
+        Instruction::Sknp(x) => format!("if v{:x} key then", x),
+        Instruction::LdLong => "i := long 0x1234".to_string(),
+        Instruction::Plane(n) => format!("plane {}", n),
+        Instruction::Audio => "audio".to_string(),
+        Instruction::LdVxDt(x) => format!("v{:x} := delay", x),
+        Instruction::LdVxK(x) => format!("v{:x} := key", x),
+        Instruction::LdDtVx(x) => format!("delay := v{:x}", x),
+        Instruction::LdStVx(x) => format!("buzzer := v{:x}", x),
+        Instruction::AddI(x) => format!("i += v{:x}", x),
+        Instruction::LdF(x) => format!("i := hex v{:x}", x),
+        Instruction::LdHf(x) => format!("i := bighex v{:x}", x),
+        Instruction::LdB(x) => format!("bcd v{:x}", x),
+        Instruction::Pitch(x) => format!("pitch := v{:x}", x),
+        Instruction::LdIVx(x) => format!("save v{:x}", x),
+        Instruction::LdVxI(x) => format!("load v{:x}", x),
+        Instruction::LdRVx(x) => format!("saveflags v{:x}", x),
+        Instruction::LdVxR(x) => format!("loadflags v{:x}", x),
+        Instruction::Unknown(_) => return None,
+    })
+}
+
+#[test]
+fn every_instruction_round_trips() {
+    for opcode in 0..=0xFFFF {
+        let instruction = decode(opcode);
+        let source = match octo(instruction) {
+            Some(source) => source,
+            None => continue,
+        };
+        // Conditionals need an instruction to skip.
+        let rom = assemble(&format!("{} clear", source)).unwrap();
+        let lines = disassemble(&rom, 0x200);
+        assert_eq!(lines[0].instruction, Some(instruction), "{}", source);
+    }
+}
+
+#[test]
+fn long_load_operand() {
+    assert_eq!(assemble("i := long 0x1234").unwrap(), vec![0xF0, 0x00, 0x12, 0x34]);
+}
+
+#[test]
+fn labels_resolve_forwards_and_backwards() {
+    let rom = assemble("
+        : start
+            jump end
+            clear
+        : end
+            start
+    ").unwrap();
+    assert_eq!(rom, vec![0x12, 0x04, 0x00, 0xE0, 0x22, 0x00]);
+}
+
+#[test]
+fn main_is_the_entry_point() {
+    let rom = assemble("
+        : sub
+            return
+        : main
+            sub
+    ").unwrap();
+    assert_eq!(rom, vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+}
+
+#[test]
+fn consts_and_aliases() {
+    let rom = assemble("
+        :const SPEED 3
+        :alias px v4
+        px := SPEED
+        px += -1
+        px -= 2
+    ").unwrap();
+    assert_eq!(rom, vec![0x64, 0x03, 0x74, 0xFF, 0x74, 0xFE]);
+}
+
+#[test]
+fn macros_substitute_arguments() {
+    let rom = assemble("
+        :macro move reg amount { reg += amount }
+        :macro twice reg { move reg 1 move reg 1 }
+        twice v2
+    ").unwrap();
+    assert_eq!(rom, vec![0x72, 0x01, 0x72, 0x01]);
+}
+
+#[test]
+fn data_bytes_and_org() {
+    let rom = assemble("
+        0x3C 0b01000010 255
+        :byte -1
+        :org 0x208
+        : data 1 2
+        i := data
+    ").unwrap();
+    assert_eq!(rom, vec![0x3C, 0x42, 0xFF, 0xFF, 0, 0, 0, 0, 0x01, 0x02, 0xA2, 0x08]);
+}
+
+#[test]
+fn if_begin_else_end() {
+    let rom = assemble("
+        if v0 == 5 begin
+            v1 := 1
+        else
+            v1 := 2
+        end
+    ").unwrap();
+    let listing: Vec<String> = disassemble(&rom, 0x200).iter()
+        .map(|line| line.instruction.unwrap().to_string())
+        .collect();
+    assert_eq!(listing, vec![
+        "SE V0, 0x05",
+        "JP 0x208",
+        "LD V1, 0x01",
+        "JP 0x20A",
+        "LD V1, 0x02",
+    ]);
+}
+
+#[test]
+fn loop_while_again() {
+    let rom = assemble("
+        loop
+            v0 += 1
+            while v0 != 10
+        again
+    ").unwrap();
+    let listing: Vec<String> = disassemble(&rom, 0x200).iter()
+        .map(|line| line.instruction.unwrap().to_string())
+        .collect();
+    assert_eq!(listing, vec![
+        "ADD V0, 0x01",
+        "SNE V0, 0x0A",
+        "JP 0x208",
+        "JP 0x200",
+    ]);
+}
+
+#[test]
+fn assemble_at_other_origin() {
+    assert_eq!(assemble_at(": here jump here", 0x600).unwrap(), vec![0x16, 0x00]);
+}
+
+#[test]
+fn errors_report_position() {
+    let error = |source: &str| assemble(source).unwrap_err();
+    assert_eq!(error("clear\n  v0 := 300"), AssembleError {
+        line: 2,
+        column: 9,
+        message: "300 does not fit in a byte".to_string(),
+    });
+    let e = error("clear\nmissing");
+    assert_eq!((e.line, e.column), (2, 1));
+    assert_eq!(e.message, "undefined label 'missing'");
+    let e = error("\n\n   sprite v0 q 1");
+    assert_eq!((e.line, e.column), (3, 14));
+    let e = error("loop\n  clear");
+    assert_eq!((e.line, e.column), (1, 1));
+    let e = error(": a\n: a");
+    assert_eq!((e.line, e.column), (2, 3));
+    let e = error("v0 := # the value is missing");
+    assert_eq!(e.message, "unexpected end of input");
+    let e = error(":calc x { 1 + 2 }");
+    assert_eq!(e.message, "unsupported directive ':calc'");
+    let e = error("v0 := 1\nv1 := 2\n:org 0x202\n  v2 := 3");
+    assert_eq!((e.line, e.column), (4, 3));
+    assert_eq!(e.message, "code at 0x202 overlaps earlier code");
+}
+
+#[test]
+fn recursive_macro_is_rejected() {
+    let e = assemble(":macro forever { forever }\nforever").unwrap_err();
+    assert_eq!(e.line, 1);
+    assert!(e.message.contains("nested too deeply"));
+}
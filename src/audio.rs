@@ -0,0 +1,364 @@
+use std::f32::consts::PI;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+
+use crate::cpu::{CHIP8_TIMER_HZ, XOCHIP_AUDIO_PATTERN_SIZE};
+use crate::error::WavError;
+
+// The buzzer: generates the tone played while the sound timer runs, as mono
+// samples between -volume and volume.
+//
+// The tone fades in and out over a few milliseconds when it is switched on
+// and off, instead of jumping to and from silence, which clicks.
+//
+// `AudioStream` drives a buzzer from the sound timer: the CPU records when
+// the timer starts and stops during a frame, and generates the frame's
+// samples at its end (see `CPU::enable_audio`). Frontends play them, or
+// write them out with `WavWriter`.
+
+pub const DEFAULT_FREQUENCY: f32 = 240.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+// Attack and release time, in seconds.
+const ENVELOPE_TIME: f32 = 0.005;
+
+// Noise picks a new level this many times per period of the tone.
+const NOISE_STEPS: f32 = 32.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise,
+    // A recording, looped at its own pitch: mono samples at `rate` Hz.
+    Sample { samples: Vec<f32>, rate: u32 },
+}
+
+impl Waveform {
+
+    // The built-in waveforms; samples are read with `read_wav`.
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "noise" => Some(Waveform::Noise),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    // In Hz; samples play at their own pitch.
+    pub frequency: f32,
+    // From 0 to 1.
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone { waveform: Waveform::Square, frequency: DEFAULT_FREQUENCY, volume: DEFAULT_VOLUME }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buzzer {
+    tone: Tone,
+    sample_rate: f32,
+    // Position within the current period, in [0, 1), or within the sample.
+    phase: f32,
+    // The envelope, from 0 (silent) to 1.
+    level: f32,
+    noise: u32,
+    noise_level: f32,
+    // An XO-CHIP pattern replaces the waveform.
+    pattern: Option<[u8; XOCHIP_AUDIO_PATTERN_SIZE]>,
+    pattern_rate: f32,
+}
+
+impl Buzzer {
+
+    pub fn new(tone: Tone, sample_rate: u32) -> Buzzer {
+        Buzzer {
+            tone,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            level: 0.0,
+            noise: 0x2545_F491,
+            noise_level: 0.0,
+            pattern: None,
+            pattern_rate: 0.0,
+        }
+    }
+
+    pub fn tone(&self) -> &Tone {
+        &self.tone
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.tone.volume = volume.clamp(0.0, 1.0);
+    }
+
+    // Plays an XO-CHIP audio pattern at `rate` bits per second instead of the
+    // waveform. `None` restores the waveform.
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; XOCHIP_AUDIO_PATTERN_SIZE]>, rate: f32) {
+        self.pattern = pattern.cloned();
+        self.pattern_rate = rate;
+    }
+
+    // Fills `out` with the next samples, fading the tone in while `on` and
+    // out otherwise.
+    pub fn fill(&mut self, out: &mut [f32], on: bool) {
+        let step = 1.0 / (ENVELOPE_TIME * self.sample_rate);
+        for x in out.iter_mut() {
+            self.level = if on { (self.level + step).min(1.0) } else { (self.level - step).max(0.0) };
+            *x = if self.level > 0.0 { self.tone.volume * self.level * self.next() } else { 0.0 };
+        }
+    }
+
+    // The next sample of the waveform at full volume.
+    fn next(&mut self) -> f32 {
+        let phase = self.phase;
+        if let Some(ref pattern) = self.pattern {
+            let bits = (XOCHIP_AUDIO_PATTERN_SIZE * 8) as f32;
+            let bit = (phase * bits) as usize;
+            self.phase = (phase + self.pattern_rate / bits / self.sample_rate) % 1.0;
+            return if (pattern[bit / 8] >> (7 - bit % 8)) & 1 == 1 { 1.0 } else { -1.0 };
+        }
+        if let Waveform::Sample { ref samples, rate } = self.tone.waveform {
+            self.phase = (phase + rate as f32 / self.sample_rate) % samples.len() as f32;
+            return samples[phase as usize];
+        }
+
+        self.phase = (phase + self.tone.frequency / self.sample_rate) % 1.0;
+        match self.tone.waveform {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 4.0 * (phase - 0.5).abs() - 1.0,
+            Waveform::Noise => {
+                if (self.phase * NOISE_STEPS) as u32 != (phase * NOISE_STEPS) as u32 {
+                    // xorshift32
+                    self.noise ^= self.noise << 13;
+                    self.noise ^= self.noise >> 17;
+                    self.noise ^= self.noise << 5;
+                    self.noise_level = self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0;
+                }
+                self.noise_level
+            }
+            Waveform::Sample { .. } => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioStream {
+    buzzer: Buzzer,
+    sample_rate: u32,
+    // Frames don't hold a whole number of samples: the sample count times 60
+    // left over from the previous frames.
+    remainder: u32,
+    // Whether the buzzer was on at the start of the frame, and is on now.
+    start: bool,
+    on: bool,
+    // When it was switched during the frame: the number of instructions
+    // executed up to then, and whether it was switched on.
+    edges: Vec<(u32, bool)>,
+    steps: u32,
+    muted: bool,
+    samples: Vec<f32>,
+}
+
+impl AudioStream {
+
+    pub fn new(tone: Tone, sample_rate: u32) -> AudioStream {
+        AudioStream {
+            buzzer: Buzzer::new(tone, sample_rate),
+            sample_rate,
+            remainder: 0,
+            start: false,
+            on: false,
+            edges: Vec::new(),
+            steps: 0,
+            muted: false,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.buzzer.tone().volume
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.buzzer.set_volume(volume);
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    // Muting fades the tone out like the sound timer running out.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    // Samples generated since the last call.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        mem::take(&mut self.samples)
+    }
+
+    // Records the state of the buzzer after an instruction.
+    pub(crate) fn step(&mut self, on: bool) {
+        self.steps += 1;
+        if on != self.on {
+            self.edges.push((self.steps, on));
+            self.on = on;
+        }
+    }
+
+    // Generates the samples of a frame, switching the buzzer at the same
+    // point of the frame as the instructions that switched it, and starts the
+    // next frame in state `on`.
+    pub(crate) fn end_frame(&mut self, pattern: Option<&[u8; XOCHIP_AUDIO_PATTERN_SIZE]>, rate: f32, on: bool) {
+        self.remainder += self.sample_rate;
+        let count = (self.remainder / CHIP8_TIMER_HZ) as usize;
+        self.remainder %= CHIP8_TIMER_HZ;
+
+        self.buzzer.set_pattern(pattern, rate);
+        let start = self.samples.len();
+        self.samples.resize(start + count, 0.0);
+        let mut state = self.start;
+        let mut from = 0;
+        for &(step, edge) in &self.edges {
+            // The first sample at or after the edge.
+            let to = ((step as usize * count).div_ceil(self.steps as usize)).min(count);
+            self.buzzer.fill(&mut self.samples[start + from..start + to], state && !self.muted);
+            state = edge;
+            from = to;
+        }
+        self.buzzer.fill(&mut self.samples[start + from..], state && !self.muted);
+
+        self.edges.clear();
+        self.steps = 0;
+        self.start = on;
+        self.on = on;
+    }
+}
+
+// Writes mono 16-bit PCM WAV files. The sizes in the header are filled in by
+// `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    // Bytes of sample data written.
+    size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        writer.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM, mono
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * 2).to_le_bytes())?;
+        // Bytes per frame, bits per sample
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data\0\0\0\0")?;
+        Ok(WavWriter { writer, sample_rate, size: 0 })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.size += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    // Fills in the header and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + self.size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// Reads a PCM (8, 16 or 32-bit) or float WAV file into a sample waveform,
+// mixing the channels down to mono.
+pub fn read_wav<R: Read>(mut reader: R) -> Result<Waveform, WavError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(WavError::Malformed);
+    }
+
+    let u16_at = |chunk: &[u8], offset: usize| u16::from_le_bytes([chunk[offset], chunk[offset + 1]]);
+    let mut format = None;
+    let mut samples = None;
+    let mut rest = &data[12..];
+    while rest.len() >= 8 {
+        let id = &rest[0..4];
+        let size = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let chunk = &rest[8..(8 + size).min(rest.len())];
+        match id {
+            b"fmt " if chunk.len() >= 16 => {
+                let mut encoding = u16_at(chunk, 0);
+                // WAVE_FORMAT_EXTENSIBLE keeps the encoding in the subformat.
+                if encoding == 0xFFFE && chunk.len() >= 26 {
+                    encoding = u16_at(chunk, 24);
+                }
+                let channels = u16_at(chunk, 2).max(1);
+                let rate = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+                format = Some((encoding, channels as usize, rate, u16_at(chunk, 14)));
+            }
+            b"data" => samples = Some(chunk),
+            _ => {}
+        }
+        // Chunks are padded to an even size.
+        rest = &rest[(8 + size + size % 2).min(rest.len())..];
+    }
+
+    let ((encoding, channels, rate, bits), samples) = match (format, samples) {
+        (Some(format), Some(samples)) => (format, samples),
+        _ => return Err(WavError::Malformed),
+    };
+    let decode: fn(&[u8]) -> f32 = match (encoding, bits) {
+        (1, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+        (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+        (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+        (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        _ => return Err(WavError::Unsupported { format: encoding, bits }),
+    };
+    let width = bits as usize / 8;
+    let samples: Vec<f32> = samples
+        .chunks_exact(width * channels)
+        .map(|frame| frame.chunks_exact(width).map(decode).sum::<f32>() / channels as f32)
+        .collect();
+    if samples.is_empty() || rate == 0 {
+        return Err(WavError::Malformed);
+    }
+    Ok(Waveform::Sample { samples, rate })
+}
+
+#[cfg(test)]
+#[path = "./audio_tests.rs"]
+mod audio_tests;
@@ -0,0 +1,168 @@
+use std::io::Cursor;
+
+use crate::audio::*;
+use crate::cpu::CPU;
+use crate::error::WavError;
+use crate::quirks::Quirks;
+
+const RATE: u32 = 8000;
+
+// A RIFF WAVE file with a PCM format chunk, an unknown chunk and `data`.
+fn wav(format: u16, channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+    let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+    wav.extend_from_slice(b"fmt \x10\0\0\0");
+    wav.extend_from_slice(&format.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&RATE.to_le_bytes());
+    wav.extend_from_slice(&(RATE * (channels * bits / 8) as u32).to_le_bytes());
+    wav.extend_from_slice(&(channels * bits / 8).to_le_bytes());
+    wav.extend_from_slice(&bits.to_le_bytes());
+    wav.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(data);
+    wav
+}
+
+fn buzzer(waveform: Waveform) -> Buzzer {
+    Buzzer::new(Tone { waveform, frequency: 1000.0, volume: 1.0 }, RATE)
+}
+
+#[test]
+fn envelope_fades_in_and_out() {
+    let mut buzzer = buzzer(Waveform::Square);
+    let mut out = [0.0; 80];
+    buzzer.fill(&mut out, true);
+    // 5 ms of attack at 8 kHz is 40 samples.
+    assert_eq!(out[0], 1.0 / 40.0);
+    assert!(out[..39].iter().all(|x| x.abs() < 1.0));
+    assert!(out[40..].iter().all(|x| x.abs() == 1.0));
+
+    buzzer.fill(&mut out, false);
+    assert!(out[0].abs() > 0.9);
+    assert!(out[40..].iter().all(|&x| x == 0.0));
+}
+
+#[test]
+fn waveforms() {
+    // 1 kHz at 8 kHz: 8 samples per period.
+    let mut out = [0.0; 48];
+    let mut square = buzzer(Waveform::Square);
+    square.fill(&mut out, true);
+    assert_eq!(&out[40..48], &[1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+
+    let mut triangle = buzzer(Waveform::Triangle);
+    triangle.fill(&mut out, true);
+    assert_eq!(&out[40..48], &[1.0, 0.5, 0.0, -0.5, -1.0, -0.5, 0.0, 0.5]);
+
+    let mut sine = buzzer(Waveform::Sine);
+    sine.fill(&mut out, true);
+    assert!((out[42] - 1.0).abs() < 1e-6 && (out[46] + 1.0).abs() < 1e-6);
+
+    let mut noise = buzzer(Waveform::Noise);
+    noise.fill(&mut out, true);
+    assert!(out[40..].iter().all(|x| x.abs() <= 1.0));
+    assert!(out[40..].windows(2).any(|pair| pair[0] != pair[1]));
+}
+
+#[test]
+fn patterns_replace_the_waveform() {
+    let mut buzzer = buzzer(Waveform::Sine);
+    // One bit per sample: 0xF0 repeated.
+    buzzer.set_pattern(Some(&[0xF0; 16]), RATE as f32);
+    let mut out = [0.0; 48];
+    buzzer.fill(&mut out, true);
+    assert_eq!(&out[40..48], &[1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+}
+
+#[test]
+fn samples_loop() {
+    let waveform = Waveform::Sample { samples: vec![0.5, -0.5, 0.25], rate: RATE / 2 };
+    let mut buzzer = buzzer(waveform);
+    buzzer.set_volume(2.0);
+    assert_eq!(buzzer.tone().volume, 1.0);
+    let mut out = [0.0; 48];
+    buzzer.fill(&mut out, true);
+    assert_eq!(&out[40..46], &[0.25, 0.25, 0.5, 0.5, -0.5, -0.5]);
+}
+
+#[test]
+fn reads_wav_files() {
+    // Stereo 16-bit, mixed down to mono.
+    let data = [0x00, 0x40, 0x00, 0x40, 0x00, 0xC0, 0x00, 0x00];
+    assert_eq!(read_wav(&wav(1, 2, 16, &data)[..]).unwrap(), Waveform::Sample { samples: vec![0.5, -0.25], rate: RATE });
+    assert_eq!(read_wav(&wav(1, 1, 8, &[0x80, 0xC0, 0x00])[..]).unwrap(), Waveform::Sample {
+        samples: vec![0.0, 0.5, -1.0],
+        rate: RATE,
+    });
+    let data = 0.75f32.to_le_bytes();
+    assert_eq!(read_wav(&wav(3, 1, 32, &data)[..]).unwrap(), Waveform::Sample { samples: vec![0.75], rate: RATE });
+}
+
+#[test]
+fn wav_errors() {
+    assert!(matches!(read_wav(&b"RIFF\0\0\0\0AVI "[..]), Err(WavError::Malformed)));
+    assert!(matches!(read_wav(&wav(1, 1, 16, &[])[..]), Err(WavError::Malformed)));
+    assert!(matches!(read_wav(&wav(1, 1, 24, &[0; 6])[..]), Err(WavError::Unsupported { format: 1, bits: 24 })));
+}
+
+// 10 instructions a frame: V0 := 2, ST := V0, then a loop.
+fn sounding_cpu(sample_rate: u32) -> CPU {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+    cpu.enable_audio(Tone { waveform: Waveform::Square, frequency: 150.0, volume: 1.0 }, sample_rate);
+    cpu
+}
+
+#[test]
+fn sound_timer_switches_the_stream_at_the_instruction() {
+    // One sample per instruction.
+    let mut cpu = sounding_cpu(600);
+    for _ in 0..4 {
+        cpu.run_frame(10).unwrap();
+    }
+    let samples = cpu.take_audio();
+    assert_eq!(samples.len(), 40);
+    // ST is set by the second instruction, and runs out at the end of the
+    // second frame.
+    assert_eq!(&samples[..2], &[0.0, 0.0]);
+    assert!(samples[2..20].iter().all(|&x| x != 0.0));
+    assert!(samples[20..23].iter().any(|&x| x != 0.0));
+    assert!(samples[23..].iter().all(|&x| x == 0.0));
+    assert!(cpu.take_audio().is_empty());
+}
+
+#[test]
+fn frames_share_out_the_samples() {
+    let mut cpu = sounding_cpu(1000);
+    let counts: Vec<usize> = (0..3)
+        .map(|_| {
+            cpu.run_frame(10).unwrap();
+            cpu.take_audio().len()
+        })
+        .collect();
+    assert_eq!(counts, vec![16, 17, 17]);
+}
+
+#[test]
+fn muted_streams_are_silent() {
+    let mut cpu = sounding_cpu(600);
+    cpu.audio_mut().unwrap().set_muted(true);
+    cpu.run_frame(10).unwrap();
+    assert!(cpu.take_audio().iter().all(|&x| x == 0.0));
+    assert!(CPU::new(Quirks::default()).take_audio().is_empty());
+}
+
+#[test]
+fn writes_wav_files() {
+    let mut writer = WavWriter::new(Cursor::new(Vec::new()), RATE).unwrap();
+    writer.write(&[0.5, -0.5]).unwrap();
+    writer.write(&[2.0]).unwrap();
+    let wav = writer.finish().unwrap().into_inner();
+    assert_eq!(wav.len(), 44 + 6);
+    assert_eq!(&wav[4..8], &42u32.to_le_bytes());
+    assert_eq!(read_wav(&wav[..]).unwrap(), Waveform::Sample {
+        samples: vec![16383.0 / 32768.0, -16383.0 / 32768.0, 32767.0 / 32768.0],
+        rate: RATE,
+    });
+}
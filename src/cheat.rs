@@ -0,0 +1,121 @@
+use std::io::{self, BufRead, Write};
+
+use crate::cpu::CPU;
+use crate::error::CheatError;
+
+// Cheats: values frozen at memory addresses, which the CPU writes back at
+// the end of every frame (see `CPU::add_cheat`), and a search for the
+// addresses holding a value of interest, such as the number of lives.
+//
+// Cheat files are text, one cheat per line: the address, the value and an
+// optional name. `#` starts a comment.
+//
+//     # Space Invaders
+//     0x2F4 3 lives
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    pub address: usize,
+    pub value: u8,
+    pub name: String,
+}
+
+// How a candidate's value must compare to its value at the previous step of
+// a search to stay a candidate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    // Equal to the given value, whatever it was before.
+    Equal(u8),
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+}
+
+impl Comparison {
+
+    pub fn matches(self, before: u8, now: u8) -> bool {
+        match self {
+            Comparison::Equal(value) => now == value,
+            Comparison::Unchanged => now == before,
+            Comparison::Changed => now != before,
+            Comparison::Increased => now > before,
+            Comparison::Decreased => now < before,
+        }
+    }
+}
+
+// Narrows down the addresses that could hold a value, by comparing memory
+// with a snapshot taken at the previous step.
+#[derive(Debug, Clone)]
+pub struct CheatSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<usize>,
+}
+
+impl CheatSearch {
+
+    // Starts with every address of memory as a candidate.
+    pub fn new(cpu: &CPU) -> CheatSearch {
+        CheatSearch { snapshot: cpu.memory().to_vec(), candidates: (0..cpu.memory().len()).collect() }
+    }
+
+    // Keeps the candidates whose value compares to the snapshot, then takes
+    // a new snapshot.
+    pub fn narrow(&mut self, cpu: &CPU, comparison: Comparison) {
+        let memory = cpu.memory();
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&address| comparison.matches(snapshot[address], memory[address]));
+        self.snapshot = memory.to_vec();
+    }
+
+    pub fn candidates(&self) -> &[usize] {
+        &self.candidates
+    }
+}
+
+pub fn read_cheats<R: BufRead>(reader: R) -> Result<Vec<Cheat>, CheatError> {
+    let mut cheats = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.splitn(3, char::is_whitespace);
+        let address = fields.next().and_then(parse_number);
+        let value = fields.next().and_then(parse_number);
+        match (address, value) {
+            (Some(address), Some(value)) if value <= 0xFF => {
+                let name = fields.next().unwrap_or("").trim().to_string();
+                cheats.push(Cheat { address, value: value as u8, name });
+            }
+            _ => return Err(CheatError::Syntax(index + 1)),
+        }
+    }
+    Ok(cheats)
+}
+
+pub fn write_cheats<W: Write>(cheats: &[Cheat], mut writer: W) -> io::Result<()> {
+    for cheat in cheats {
+        if cheat.name.is_empty() {
+            writeln!(writer, "{:#05X} {}", cheat.address, cheat.value)?;
+        } else {
+            writeln!(writer, "{:#05X} {} {}", cheat.address, cheat.value, cheat.name)?;
+        }
+    }
+    Ok(())
+}
+
+// A decimal or 0x-prefixed hexadecimal number.
+fn parse_number(value: &str) -> Option<usize> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        usize::from_str_radix(&value[2..], 16).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+#[cfg(test)]
+#[path = "./cheat_tests.rs"]
+mod cheat_tests;
@@ -0,0 +1,80 @@
+use crate::cheat::*;
+use crate::cpu::CPU;
+use crate::error::CheatError;
+use crate::quirks::Quirks;
+
+static PROGRAM: [u8; 8] = [
+    0xA3, 0x00, // LD I, 0x300
+    0xF0, 0x33, // LD B, V0
+    0x70, 0x05, // ADD V0, 0x05
+    0x12, 0x02, // JP 0x202
+];
+
+#[test]
+fn search_narrows_candidates() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&PROGRAM).unwrap();
+    let mut search = CheatSearch::new(&cpu);
+    assert_eq!(search.candidates().len(), cpu.memory().len());
+
+    // The second LD B stores V0 = 5, so only its ones digit at 0x302 changes.
+    cpu.run_frame(5).unwrap();
+    search.narrow(&cpu, Comparison::Changed);
+    assert_eq!(search.candidates(), &[0x302]);
+    search.narrow(&cpu, Comparison::Unchanged);
+    assert_eq!(search.candidates(), &[0x302]);
+    // 10: the ones digit drops to 0.
+    cpu.run_frame(3).unwrap();
+    search.narrow(&cpu, Comparison::Decreased);
+    assert_eq!(search.candidates(), &[0x302]);
+    search.narrow(&cpu, Comparison::Equal(1));
+    assert!(search.candidates().is_empty());
+}
+
+#[test]
+fn comparisons() {
+    assert!(Comparison::Equal(3).matches(7, 3));
+    assert!(Comparison::Decreased.matches(7, 3));
+    assert!(!Comparison::Decreased.matches(3, 3));
+    assert!(Comparison::Unchanged.matches(3, 3));
+}
+
+#[test]
+fn frozen_values_are_rewritten_every_frame() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&PROGRAM).unwrap();
+    cpu.add_cheat(Cheat { address: 0x302, value: 9, name: "lives".to_string() });
+    assert_eq!(cpu.memory()[0x302], 9);
+    cpu.run_frame(4).unwrap();
+    assert_eq!(cpu.memory()[0x302], 9);
+
+    cpu.add_cheat(Cheat { address: 0x302, value: 1, name: String::new() });
+    assert_eq!(cpu.cheats().len(), 1);
+    cpu.reset();
+    assert_eq!(cpu.cheats().len(), 1);
+    assert!(cpu.remove_cheat(0x302));
+    assert!(!cpu.remove_cheat(0x302));
+}
+
+#[test]
+fn cheat_files_roundtrip() {
+    let text = "# Space Invaders\n0x2F4 3 lives left\n\n768 0xFF  # score\n";
+    let cheats = read_cheats(text.as_bytes()).unwrap();
+    assert_eq!(cheats, vec![
+        Cheat { address: 0x2F4, value: 3, name: "lives left".to_string() },
+        Cheat { address: 0x300, value: 0xFF, name: String::new() },
+    ]);
+    let mut file = Vec::new();
+    write_cheats(&cheats, &mut file).unwrap();
+    assert_eq!(String::from_utf8(file.clone()).unwrap(), "0x2F4 3 lives left\n0x300 255\n");
+    assert_eq!(read_cheats(&file[..]).unwrap(), cheats);
+}
+
+#[test]
+fn cheat_file_errors_name_the_line() {
+    match read_cheats("0x200 1\n0x201 256\n".as_bytes()) {
+        Err(CheatError::Syntax(2)) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(read_cheats("lives\n".as_bytes()), Err(CheatError::Syntax(1))));
+}
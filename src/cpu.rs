@@ -1,45 +1,118 @@
-use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read, Write};
+use std::mem;
+
 use rand;
-use rand::Rng;
 
-const CHIP8_RAM_SIZE: usize = 4096;
+use crate::audio::{AudioStream, Tone};
+use crate::cheat::Cheat;
+use crate::error::{CpuError, LoadError, StateError};
+use crate::font::{
+    Font, BIG_FONT, BIG_FONT_SIZE, BIG_FONT_SPRITE_SIZE, DEFAULT_FONT_ADDRESS, FONT_SIZE, FONT_SPRITE_SIZE,
+};
+use crate::instruction::{decode, Instruction};
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::rng::{RandomSource, SplitMix64};
+use crate::state::{fnv1a, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+use crate::watch::{Access, Target, WatchHit, Watchpoint};
+
+pub const CHIP8_RAM_SIZE: usize = 0x1000;
+pub const XOCHIP_RAM_SIZE: usize = 0x10000;
 pub const CHIP8_GFX_WIDTH: usize = 64;
 pub const CHIP8_GFX_HEIGHT: usize = 32;
+// SUPER-CHIP high resolution mode.
+pub const SCHIP_GFX_WIDTH: usize = 128;
+pub const SCHIP_GFX_HEIGHT: usize = 64;
+const SCHIP_N_FLAGS: usize = 16;
+// XO-CHIP audio pattern buffer: 128 1-bit samples.
+pub const XOCHIP_AUDIO_PATTERN_SIZE: usize = 16;
+const XOCHIP_DEFAULT_PITCH: u8 = 64;
 const CHIP8_N_REGISTERS: usize = 16;
-const CHIP8_N_KEYS: usize = 16;
+pub const CHIP8_N_KEYS: usize = 16;
 const CHIP8_STACK_DEPTH: usize = 16;
-const CHIP8_PROGRAM_START: usize = 0x200;
+pub const CHIP8_PROGRAM_START: usize = 0x200;
+// Programs for the ETI-660 start at 0x600 instead.
+pub const ETI660_PROGRAM_START: usize = 0x600;
+pub const CHIP8_TIMER_HZ: u32 = 60;
+// Far beyond any real machine, and low enough for the frame loops' arithmetic.
+pub const MAX_IPS: u32 = 10_000_000;
+
+// What a call to `CPU::step` did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepOutcome {
+    // An instruction was executed.
+    Executed,
+    // Execution is blocked on Fx0A until a key is pressed.
+    WaitingForKey,
+    // The program ended with 00FD and will not execute any further.
+    Exited,
+}
+
+// Large enough for the high resolution mode. In low resolution only the
+// top-left 64x32 pixels are used. Each pixel holds one bit per XO-CHIP
+// bitplane, so plain CHIP-8 programs only ever produce 0 and 1.
+pub type Framebuffer = [[u8; SCHIP_GFX_WIDTH]; SCHIP_GFX_HEIGHT];
 
 #[derive(Debug, PartialEq)]
 enum ProgramCounter {
     Next,
     Skip,
     Jump(usize),
+    Exit,
 }
 
 pub struct CPU {
-    ram: [u8; CHIP8_RAM_SIZE],
-    pub vram: [[u8; CHIP8_GFX_WIDTH]; CHIP8_GFX_HEIGHT],
+    ram: Vec<u8>,
+    vram: Framebuffer,
+    hires: bool,
+    plane: u8,
+    audio_pattern: Option<[u8; XOCHIP_AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
     v: [u8; CHIP8_N_REGISTERS],
     i: usize,
     pc: usize,
     stack: [usize; CHIP8_STACK_DEPTH],
     sp: usize,
     delay_timer: u8,
-    pub sound_timer: u8,
+    sound_timer: u8,
     keypad: [bool; CHIP8_N_KEYS],
     waiting_keypad: bool,
     waiting_keypad_register: usize,
-    pub redraw: bool
+    // The key pressed during an Fx0A wait, if it is yet to be released.
+    waiting_keypad_key: Option<usize>,
+    redraw: bool,
+    exited: bool,
+    rpl: [u8; SCHIP_N_FLAGS],
+    quirks: Quirks,
+    font: Font,
+    font_address: usize,
+    big_font_address: usize,
+    rom_hash: u64,
+    rng: Box<dyn RandomSource>,
+    watchpoints: Vec<Watchpoint>,
+    cheats: Vec<Cheat>,
+    audio: Option<AudioStream>,
+    // Hits since the last `take_watch_hits`.
+    watch_hits: Vec<WatchHit>,
+    // The instruction being executed, for watch hits.
+    opcode: u16,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        CPU::new(Quirks::default())
+    }
 }
 
 impl CPU {
 
-    pub fn new() -> CPU {
+    pub fn new(quirks: Quirks) -> CPU {
         let mut cpu = CPU {
-            ram: [0; CHIP8_RAM_SIZE],
-            vram: [[0; CHIP8_GFX_WIDTH]; CHIP8_GFX_HEIGHT],
+            ram: vec![0; quirks.memory_size],
+            vram: [[0; SCHIP_GFX_WIDTH]; SCHIP_GFX_HEIGHT],
+            hires: false,
+            plane: 0x1,
+            audio_pattern: None,
+            pitch: XOCHIP_DEFAULT_PITCH,
             v: [0; CHIP8_N_REGISTERS],
             i: 0,
             pc: CHIP8_PROGRAM_START,
@@ -50,296 +123,964 @@ impl CPU {
             keypad: [false; CHIP8_N_KEYS],
             waiting_keypad: false,
             waiting_keypad_register: 0,
-            redraw: false
+            waiting_keypad_key: None,
+            redraw: false,
+            exited: false,
+            rpl: [0; SCHIP_N_FLAGS],
+            quirks,
+            font: Font::default(),
+            font_address: DEFAULT_FONT_ADDRESS,
+            big_font_address: DEFAULT_FONT_ADDRESS + FONT_SIZE,
+            rom_hash: fnv1a(&[]),
+            rng: Box::new(SplitMix64::new(rand::random())),
+            watchpoints: Vec::new(),
+            cheats: Vec::new(),
+            audio: None,
+            watch_hits: Vec::new(),
+            opcode: 0,
         };
-        for i in 0..FONT_SET.len() {
-            cpu.ram[0x50+i] = FONT_SET[i]
-        }
+        cpu.copy_font(Font::default(), DEFAULT_FONT_ADDRESS);
         cpu
     }
 
-    pub fn load_game(&mut self, file: &mut File) {
-        for byte in file.bytes() {
-            match byte {
-                Ok(b) => {
-                    self.ram[self.pc] = b;
-                    self.pc += 1;
-                }
-                Err(_e) => {
+    // Copies the font sprites to `address`, followed by the SUPER-CHIP big font,
+    // and points Fx29 and Fx30 at them. Both must fit below the program start address.
+    pub fn load_font(&mut self, font: Font, address: usize) -> Result<(), LoadError> {
+        match address.checked_add(FONT_SIZE + BIG_FONT_SIZE) {
+            Some(end) if end <= CHIP8_PROGRAM_START => {
+                self.copy_font(font, address);
+                Ok(())
+            }
+            _ => Err(LoadError::InvalidFontAddress(address)),
+        }
+    }
 
-                }
+    // `load_font` without the check, for fonts already known to fit.
+    fn copy_font(&mut self, font: Font, address: usize) {
+        let big_font_address = address + FONT_SIZE;
+        self.ram[address..big_font_address].copy_from_slice(font.sprites());
+        self.ram[big_font_address..big_font_address + BIG_FONT_SIZE].copy_from_slice(&BIG_FONT);
+        self.font = font;
+        self.font_address = address;
+        self.big_font_address = big_font_address;
+    }
+
+    // Puts the machine back in its power-on state. Quirks, font, the random
+    // source, watchpoints, cheats and audio output are kept.
+    pub fn reset(&mut self) {
+        let (font, font_address) = (self.font, self.font_address);
+        let rng = mem::replace(&mut self.rng, Box::new(SplitMix64::new(0)));
+        let watchpoints = mem::take(&mut self.watchpoints);
+        let cheats = mem::take(&mut self.cheats);
+        let audio = self.audio.take();
+        *self = CPU::new(self.quirks);
+        self.copy_font(font, font_address);
+        self.rng = rng;
+        self.watchpoints = watchpoints;
+        self.cheats = cheats;
+        self.audio = audio;
+    }
+
+    // Replaces the random source used by Cxkk.
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    // Makes Cxkk deterministic by seeding the default random source.
+    pub fn seed(&mut self, seed: u64) {
+        self.set_rng(Box::new(SplitMix64::new(seed)));
+    }
+
+    // Resets the machine and loads a ROM image at the program start address.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        self.load_rom_at(rom, CHIP8_PROGRAM_START)
+    }
+
+    // Resets the machine and loads a ROM image at `address`, where execution will start.
+    pub fn load_rom_at(&mut self, rom: &[u8], address: usize) -> Result<(), LoadError> {
+        if !(CHIP8_PROGRAM_START..self.ram.len()).contains(&address) {
+            return Err(LoadError::InvalidAddress(address));
+        }
+        let max = self.ram.len() - address;
+        if rom.len() > max {
+            return Err(LoadError::TooLarge { size: rom.len(), max });
+        }
+        self.reset();
+        self.ram[address..address + rom.len()].copy_from_slice(rom);
+        self.pc = address;
+        self.rom_hash = fnv1a(rom);
+        Ok(())
+    }
+
+    // Reads a ROM image to the end and loads it at the program start address.
+    pub fn load_rom_from<R: Read>(&mut self, reader: &mut R) -> Result<(), LoadError> {
+        self.load_rom_from_at(reader, CHIP8_PROGRAM_START)
+    }
+
+    pub fn load_rom_from_at<R: Read>(&mut self, reader: &mut R, address: usize) -> Result<(), LoadError> {
+        let mut rom = Vec::new();
+        reader.read_to_end(&mut rom)?;
+        self.load_rom_at(&rom, address)
+    }
+
+    // Hash of the loaded ROM image, identifying it in save states.
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    // Writes the complete machine state. Quirks and the font choice are configuration
+    // and are not included; the state can only be restored into a machine running
+    // the same ROM.
+    pub fn save_state<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut w = StateWriter::new(writer);
+        w.bytes(&STATE_MAGIC)?;
+        w.u16(STATE_VERSION)?;
+        w.u64(self.rom_hash)?;
+        w.u32(self.ram.len() as u32)?;
+        w.bytes(&self.ram)?;
+        for row in self.vram.iter() {
+            w.bytes(row)?;
+        }
+        w.bool(self.hires)?;
+        w.u8(self.plane)?;
+        w.bool(self.audio_pattern.is_some())?;
+        w.bytes(&self.audio_pattern.unwrap_or([0; XOCHIP_AUDIO_PATTERN_SIZE]))?;
+        w.u8(self.pitch)?;
+        w.bytes(&self.v)?;
+        w.u32(self.i as u32)?;
+        w.u32(self.pc as u32)?;
+        for &address in self.stack.iter() {
+            w.u32(address as u32)?;
+        }
+        w.u8(self.sp as u8)?;
+        w.u8(self.delay_timer)?;
+        w.u8(self.sound_timer)?;
+        for &pressed in self.keypad.iter() {
+            w.bool(pressed)?;
+        }
+        w.bool(self.waiting_keypad)?;
+        w.u8(self.waiting_keypad_register as u8)?;
+        w.bool(self.waiting_keypad_key.is_some())?;
+        w.u8(self.waiting_keypad_key.unwrap_or(0) as u8)?;
+        w.bool(self.exited)?;
+        w.bytes(&self.rpl)?;
+        w.u32(self.font_address as u32)?;
+        w.u32(self.big_font_address as u32)?;
+        w.u64(self.rng.state())?;
+        Ok(())
+    }
+
+    // Restores a state written by `save_state`. On error the machine is unchanged.
+    pub fn load_state<R: Read>(&mut self, reader: R) -> Result<(), StateError> {
+        let mut r = StateReader::new(reader);
+        let mut magic = [0; 4];
+        r.bytes(&mut magic)?;
+        if magic != STATE_MAGIC {
+            return Err(StateError::NotAState);
+        }
+        let version = r.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let rom_hash = r.u64()?;
+        if rom_hash != self.rom_hash {
+            return Err(StateError::RomMismatch { expected: self.rom_hash, found: rom_hash });
+        }
+        let ram_size = r.u32()? as usize;
+        if ram_size != self.ram.len() {
+            return Err(StateError::MemorySizeMismatch { expected: self.ram.len(), found: ram_size });
+        }
+
+        let mut state = CPU::new(self.quirks);
+        state.font = self.font;
+        state.rom_hash = rom_hash;
+        r.bytes(&mut state.ram)?;
+        for row in state.vram.iter_mut() {
+            r.bytes(row)?;
+        }
+        state.hires = r.bool()?;
+        state.plane = r.u8()?;
+        let has_pattern = r.bool()?;
+        let mut pattern = [0; XOCHIP_AUDIO_PATTERN_SIZE];
+        r.bytes(&mut pattern)?;
+        state.audio_pattern = if has_pattern { Some(pattern) } else { None };
+        state.pitch = r.u8()?;
+        r.bytes(&mut state.v)?;
+        state.i = r.u32()? as usize;
+        state.pc = r.u32()? as usize;
+        for address in state.stack.iter_mut() {
+            *address = r.u32()? as usize;
+        }
+        state.sp = r.u8()? as usize;
+        if state.sp > CHIP8_STACK_DEPTH {
+            return Err(StateError::Corrupt("stack pointer"));
+        }
+        state.delay_timer = r.u8()?;
+        state.sound_timer = r.u8()?;
+        for pressed in state.keypad.iter_mut() {
+            *pressed = r.bool()?;
+        }
+        state.waiting_keypad = r.bool()?;
+        state.waiting_keypad_register = r.u8()? as usize;
+        if state.waiting_keypad_register >= CHIP8_N_REGISTERS {
+            return Err(StateError::Corrupt("key wait register"));
+        }
+        let has_key = r.bool()?;
+        let key = r.u8()? as usize;
+        if key >= CHIP8_N_KEYS {
+            return Err(StateError::Corrupt("key wait key"));
+        }
+        state.waiting_keypad_key = if has_key { Some(key) } else { None };
+        state.exited = r.bool()?;
+        r.bytes(&mut state.rpl)?;
+        state.font_address = r.u32()? as usize;
+        state.big_font_address = r.u32()? as usize;
+        let rng_state = r.u64()?;
+        state.redraw = true;
+
+        state.rng = mem::replace(&mut self.rng, Box::new(SplitMix64::new(0)));
+        state.rng.set_state(rng_state);
+        state.watchpoints = mem::take(&mut self.watchpoints);
+        state.cheats = mem::take(&mut self.cheats);
+        state.audio = self.audio.take();
+        *self = state;
+        Ok(())
+    }
+
+    // Framebuffer, one byte per pixel (0 = off, 1 = on). Only the area given by
+    // `resolution` is in use.
+    pub fn vram(&self) -> &Framebuffer {
+        &self.vram
+    }
+
+    // Current display size in pixels: 64x32, or 128x64 in high resolution mode.
+    pub fn resolution(&self) -> (usize, usize) {
+        if self.hires {
+            (SCHIP_GFX_WIDTH, SCHIP_GFX_HEIGHT)
+        } else {
+            (CHIP8_GFX_WIDTH, CHIP8_GFX_HEIGHT)
+        }
+    }
+
+    // The XO-CHIP audio pattern loaded by F002, if any. Programs that never load
+    // one expect the plain CHIP-8 buzzer.
+    pub fn audio_pattern(&self) -> Option<&[u8; XOCHIP_AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    // Rate in Hz at which the audio pattern bits are played back, set by Fx3A.
+    pub fn audio_playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    // Whether the program has ended with 00FD.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    // Whether the last executed instruction touched the framebuffer.
+    pub fn redraw(&self) -> bool {
+        self.redraw
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn font(&self) -> Font {
+        self.font
+    }
+
+    pub fn font_address(&self) -> usize {
+        self.font_address
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    // Address of the next instruction.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    pub fn i(&self) -> usize {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: usize) {
+        self.i = i;
+    }
+
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn set_v(&mut self, x: usize, value: u8) {
+        self.v[x & 0xF] = value;
+    }
+
+    // Addresses of the calls to the subroutines in progress, outermost first.
+    pub fn stack(&self) -> &[usize] {
+        &self.stack[..self.sp]
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    // Returns false, and sets nothing, for a register past VF.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if let Watchpoint::Register(x) = watchpoint {
+            if x >= CHIP8_N_REGISTERS {
+                return false;
             }
         }
-        self.pc = 0x200;
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+        true
+    }
+
+    // Returns whether the watchpoint was set.
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Watchpoint hits since the last call, in the order they happened.
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        mem::take(&mut self.watch_hits)
+    }
+
+    // Freezes the byte at the cheat's address to its value, replacing any
+    // cheat on the same address. The value is written right away, and again
+    // at the end of every frame.
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        self.remove_cheat(cheat.address);
+        if let Some(byte) = self.ram.get_mut(cheat.address) {
+            *byte = cheat.value;
+        }
+        self.cheats.push(cheat);
+    }
+
+    // Returns whether a cheat was set on `address`.
+    pub fn remove_cheat(&mut self, address: usize) -> bool {
+        let count = self.cheats.len();
+        self.cheats.retain(|cheat| cheat.address != address);
+        self.cheats.len() != count
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.cheats.clear();
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    // Generates `sample_rate` samples per second of the buzzer playing while
+    // the sound timer runs, switched on and off at the instruction that set
+    // the timer, or at the frame whose tick stopped it. Collect them with
+    // `take_audio`.
+    pub fn enable_audio(&mut self, tone: Tone, sample_rate: u32) {
+        self.audio = Some(AudioStream::new(tone, sample_rate));
     }
 
-    pub fn tick(&mut self, keypad: [bool; 16]) {
+    pub fn audio(&self) -> Option<&AudioStream> {
+        self.audio.as_ref()
+    }
+
+    pub fn audio_mut(&mut self) -> Option<&mut AudioStream> {
+        self.audio.as_mut()
+    }
+
+    // Samples generated since the last call; none unless audio is enabled.
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        self.audio.as_mut().map(AudioStream::take_samples).unwrap_or_default()
+    }
+
+    // Whether Fx0A is blocking until a key is pressed.
+    pub fn waiting_for_key(&self) -> bool {
+        self.waiting_keypad
+    }
+
+    pub fn set_keypad(&mut self, keypad: [bool; CHIP8_N_KEYS]) {
         self.keypad = keypad;
+    }
+
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        self.keypad[key & 0xF] = pressed;
+    }
+
+    pub fn tick(&mut self, keypad: [bool; CHIP8_N_KEYS]) -> Result<StepOutcome, CpuError> {
+        self.set_keypad(keypad);
+        self.step()
+    }
+
+    // Executes a single instruction using the current keypad state.
+    // On error the machine is left untouched, pointing at the faulting instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, CpuError> {
+        let outcome = self.execute();
+        if let Some(ref mut audio) = self.audio {
+            audio.step(self.sound_timer > 0);
+        }
+        outcome
+    }
+
+    fn execute(&mut self) -> Result<StepOutcome, CpuError> {
         self.redraw = false;
-        if !self.waiting_keypad {
-            if self.delay_timer > 0 {
-                self.delay_timer -= 1;
+        if self.exited {
+            Ok(StepOutcome::Exited)
+        } else if !self.waiting_keypad {
+            let opcode = self.get_opcode()?;
+            self.opcode = opcode;
+            let (pc, v, i) = (self.pc, self.v, self.i);
+            self.exec_opcode(opcode)?;
+            if !self.watchpoints.is_empty() {
+                self.check_registers(pc, v, i);
             }
-            if self.sound_timer > 0 {
-                self.sound_timer -= 1;
-            }
-            let opcode = self.get_opcode();
-            self.exec_opcode(opcode);
+            Ok(if self.exited { StepOutcome::Exited } else { StepOutcome::Executed })
         } else {
-            for i in 0..self.keypad.len() {
-                if self.keypad[i] {
-                    self.v[self.waiting_keypad_register] = i as u8;
-                    self.waiting_keypad = false;
-                    self.waiting_keypad_register = 0x0;
-                    break;
+            let v = self.v;
+            let outcome = self.wait_for_key();
+            if !self.watchpoints.is_empty() {
+                // Fx0A itself is at the address before PC.
+                self.check_registers(self.pc - 2, v, self.i);
+            }
+            Ok(outcome)
+        }
+    }
+
+    // One step of an Fx0A wait. The wait ends when a key is pressed, or with
+    // `key_wait_release` when the first key pressed is released again.
+    fn wait_for_key(&mut self) -> StepOutcome {
+        let key = match self.waiting_keypad_key {
+            Some(key) if self.keypad[key] => return StepOutcome::WaitingForKey,
+            Some(key) => key,
+            None => match self.keypad.iter().position(|&pressed| pressed) {
+                Some(key) if self.quirks.key_wait_release => {
+                    self.waiting_keypad_key = Some(key);
+                    return StepOutcome::WaitingForKey;
                 }
+                Some(key) => key,
+                None => return StepOutcome::WaitingForKey,
+            },
+        };
+        self.v[self.waiting_keypad_register] = key as u8;
+        self.waiting_keypad = false;
+        self.waiting_keypad_register = 0x0;
+        self.waiting_keypad_key = None;
+        StepOutcome::Executed
+    }
+
+    // Runs one 60 Hz frame: `instructions` instructions followed by a timer tick.
+    // Returns whether the framebuffer changed during the frame.
+    pub fn run_frame(&mut self, instructions: u32) -> Result<bool, CpuError> {
+        let mut redraw = false;
+        for _ in 0..instructions {
+            let outcome = self.step()?;
+            redraw |= self.redraw;
+            if outcome == StepOutcome::Exited {
+                break;
             }
         }
+        self.end_frame();
+        Ok(redraw)
     }
 
-    fn get_opcode(&self) -> u16 {
-        (self.ram[self.pc] as u16) << 8 | (self.ram[self.pc + 1] as u16)
-    }
-
-    fn exec_opcode(&mut self, opcode: u16) {
-
-        let nibbles = (
-            (opcode & 0xF000) >> 12 as u8,
-            (opcode & 0x0F00) >> 8 as u8,
-            (opcode & 0x00F0) >> 4 as u8,
-            (opcode & 0x000F) as u8,
-        );
-
-        let nnn = (opcode & 0x0FFF) as usize;
-        let kk = (opcode & 0x00FF) as u8;
-        let x = nibbles.1 as usize;
-        let y = nibbles.2 as usize;
-        let n = nibbles.3 as usize;
-
-        let pc: Option<ProgramCounter> = match nibbles {
-            (0x0, 0x0, 0xE, 0x0) => Some(self.op_00e0()),
-            (0x0, 0x0, 0xE, 0xE) => Some(self.op_00ee()),
-            (0x1, _,   _,   _  ) => Some(self.op_1nnn(nnn)),
-            (0x2, _,   _,   _  ) => Some(self.op_2nnn(nnn)),
-            (0x3, _,   _,   _  ) => Some(self.op_3xkk(x, kk)),
-            (0x4, _,   _,   _  ) => Some(self.op_4xkk(x, kk)),
-            (0x5, _,   _,   0x0) => Some(self.op_5xy0(x ,y)),
-            (0x6, _,   _,   _  ) => Some(self.op_6xkk(x, kk)),
-            (0x7, _,   _,   _  ) => Some(self.op_7xkk(x, kk)),
-            (0x8, _,   _,   0x0) => Some(self.op_8xy0(x, y)),
-            (0x8, _,   _,   0x1) => Some(self.op_8xy1(x, y)),
-            (0x8, _,   _,   0x2) => Some(self.op_8xy2(x, y)),
-            (0x8, _,   _,   0x3) => Some(self.op_8xy3(x, y)),
-            (0x8, _,   _,   0x4) => Some(self.op_8xy4(x, y)),
-            (0x8, _,   _,   0x5) => Some(self.op_8xy5(x, y)),
-            (0x8, _,   _,   0x6) => Some(self.op_8xy6(x)),
-            (0x8, _,   _,   0x7) => Some(self.op_8xy7(x, y)),
-            (0x8, _,   _,   0xE) => Some(self.op_8xye(x)),
-            (0x9, _,   _,   0x0) => Some(self.op_9xy0(x, y)),
-            (0xA, _,   _,   _  ) => Some(self.op_annn(nnn)),
-            (0xB, _,   _,   _  ) => Some(self.op_bnnn(nnn)),
-            (0xC, _,   _,   _  ) => Some(self.op_cxkk(x, kk)),
-            (0xD, _,   _,   _  ) => Some(self.op_dxyn(x, y, n)),
-            (0xE, _,   0x9, 0xE) => Some(self.op_ex9e(x)),
-            (0xE, _,   0xA, 0x1) => Some(self.op_exa1(x)),
-            (0xF, _,   0x0, 0x7) => Some(self.op_fx07(x)),
-            (0xF, _,   0x0, 0xA) => Some(self.op_fx0a(x)),
-            (0xF, _,   0x1, 0x5) => Some(self.op_fx15(x)),
-            (0xF, _,   0x1, 0x8) => Some(self.op_fx18(x)),
-            (0xF, _,   0x1, 0xE) => Some(self.op_fx1e(x)),
-            (0xF, _,   0x2, 0x9) => Some(self.op_fx29(x)),
-            (0xF, _,   0x3, 0x3) => Some(self.op_fx33(x)),
-            (0xF, _,   0x5, 0x5) => Some(self.op_fx55(x)),
-            (0xF, _,   0x6, 0x5) => Some(self.op_fx65(x)),
-            _ => None
+    // Ends a 60 Hz frame: ticks the timers, generates the frame's audio and
+    // rewrites frozen cheat values. Frontends that step the CPU themselves
+    // call this once per frame.
+    pub fn end_frame(&mut self) {
+        self.tick_timers();
+        let rate = self.audio_playback_rate();
+        if let Some(ref mut audio) = self.audio {
+            audio.end_frame(self.audio_pattern.as_ref(), rate, self.sound_timer > 0);
+        }
+        for cheat in &self.cheats {
+            if let Some(byte) = self.ram.get_mut(cheat.address) {
+                *byte = cheat.value;
+            }
+        }
+    }
+
+    // Decrements the delay and sound timers. Must be called at 60 Hz,
+    // independently of how many instructions are executed per second.
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+
+    // Fetches don't trigger watchpoints.
+    fn get_opcode(&self) -> Result<u16, CpuError> {
+        let byte = |address: usize| match self.ram.get(address) {
+            Some(&byte) => Ok(byte as u16),
+            None => Err(CpuError::MemoryOutOfBounds { pc: self.pc, address }),
+        };
+        Ok(byte(self.pc)? << 8 | byte(self.pc + 1)?)
+    }
+
+    // Reads a byte of memory on behalf of the instruction at PC.
+    fn read(&mut self, address: usize) -> Result<u8, CpuError> {
+        match self.ram.get(address) {
+            Some(&byte) => {
+                self.watch(address, Access::Read, byte, byte);
+                Ok(byte)
+            }
+            None => Err(CpuError::MemoryOutOfBounds { pc: self.pc, address }),
+        }
+    }
+
+    // Writes a byte of memory on behalf of the instruction at PC.
+    fn write(&mut self, address: usize, byte: u8) -> Result<(), CpuError> {
+        match self.ram.get_mut(address) {
+            Some(cell) => {
+                let old = mem::replace(cell, byte);
+                self.watch(address, Access::Write, old, byte);
+                Ok(())
+            }
+            None => Err(CpuError::MemoryOutOfBounds { pc: self.pc, address }),
+        }
+    }
+
+    // Records a hit if a watchpoint covers the access.
+    fn watch(&mut self, address: usize, access: Access, old: u8, new: u8) {
+        if self.watchpoints.iter().any(|watchpoint| watchpoint.covers(address, access)) {
+            self.watch_hits.push(WatchHit {
+                pc: self.pc,
+                opcode: self.opcode,
+                target: Target::Memory(address),
+                access,
+                old: old as usize,
+                new: new as usize,
+            });
+        }
+    }
+
+    // Records hits for watched registers that differ from `v` and `i`, their
+    // values before the instruction at `pc`.
+    fn check_registers(&mut self, pc: usize, v: [u8; CHIP8_N_REGISTERS], i: usize) {
+        for watchpoint in &self.watchpoints {
+            let (target, old, new) = match *watchpoint {
+                Watchpoint::Register(x) => (Target::Register(x), v[x] as usize, self.v[x] as usize),
+                Watchpoint::Index => (Target::Index, i, self.i),
+                Watchpoint::Memory { .. } => continue,
+            };
+            if old != new {
+                self.watch_hits.push(WatchHit {
+                    pc,
+                    opcode: self.opcode,
+                    target,
+                    access: Access::Write,
+                    old,
+                    new,
+                });
+            }
+        }
+    }
+
+    // Fails unless `len` bytes starting at `address` are all in memory, so that
+    // multi-byte instructions never fault halfway through.
+    fn check_range(&self, address: usize, len: usize) -> Result<(), CpuError> {
+        if address.checked_add(len).is_none_or(|end| end > self.ram.len()) {
+            let address = address.max(self.ram.len());
+            return Err(CpuError::MemoryOutOfBounds { pc: self.pc, address });
+        }
+        Ok(())
+    }
+
+    fn exec_opcode(&mut self, opcode: u16) -> Result<(), CpuError> {
+
+        let pc = match decode(opcode) {
+            Instruction::Scd(n) => self.op_00cn(n)?,
+            Instruction::Cls => self.op_00e0()?,
+            Instruction::Ret => self.op_00ee()?,
+            Instruction::Scr => self.op_00fb()?,
+            Instruction::Scl => self.op_00fc()?,
+            Instruction::Exit => self.op_00fd()?,
+            Instruction::Low => self.op_00fe()?,
+            Instruction::High => self.op_00ff()?,
+            Instruction::Jp(nnn) => self.op_1nnn(nnn)?,
+            Instruction::Call(nnn) => self.op_2nnn(nnn)?,
+            Instruction::SeByte { x, kk } => self.op_3xkk(x, kk)?,
+            Instruction::SneByte { x, kk } => self.op_4xkk(x, kk)?,
+            Instruction::SeReg { x, y } => self.op_5xy0(x, y)?,
+            Instruction::SaveRange { x, y } => self.op_5xy2(x, y)?,
+            Instruction::LoadRange { x, y } => self.op_5xy3(x, y)?,
+            Instruction::LdByte { x, kk } => self.op_6xkk(x, kk)?,
+            Instruction::AddByte { x, kk } => self.op_7xkk(x, kk)?,
+            Instruction::LdReg { x, y } => self.op_8xy0(x, y)?,
+            Instruction::Or { x, y } => self.op_8xy1(x, y)?,
+            Instruction::And { x, y } => self.op_8xy2(x, y)?,
+            Instruction::Xor { x, y } => self.op_8xy3(x, y)?,
+            Instruction::AddReg { x, y } => self.op_8xy4(x, y)?,
+            Instruction::Sub { x, y } => self.op_8xy5(x, y)?,
+            Instruction::Shr { x, y } => self.op_8xy6(x, y)?,
+            Instruction::Subn { x, y } => self.op_8xy7(x, y)?,
+            Instruction::Shl { x, y } => self.op_8xye(x, y)?,
+            Instruction::SneReg { x, y } => self.op_9xy0(x, y)?,
+            Instruction::LdI(nnn) => self.op_annn(nnn)?,
+            Instruction::JpV0(nnn) => self.op_bnnn(nnn >> 8, nnn)?,
+            Instruction::Rnd { x, kk } => self.op_cxkk(x, kk)?,
+            Instruction::Drw { x, y, n } => self.op_dxyn(x, y, n)?,
+            Instruction::Skp(x) => self.op_ex9e(x)?,
+            Instruction::Sknp(x) => self.op_exa1(x)?,
+            Instruction::LdLong => self.op_f000()?,
+            Instruction::Plane(n) => self.op_fn01(n)?,
+            Instruction::Audio => self.op_f002()?,
+            Instruction::LdVxDt(x) => self.op_fx07(x)?,
+            Instruction::LdVxK(x) => self.op_fx0a(x)?,
+            Instruction::LdDtVx(x) => self.op_fx15(x)?,
+            Instruction::LdStVx(x) => self.op_fx18(x)?,
+            Instruction::AddI(x) => self.op_fx1e(x)?,
+            Instruction::LdF(x) => self.op_fx29(x)?,
+            Instruction::LdHf(x) => self.op_fx30(x)?,
+            Instruction::LdB(x) => self.op_fx33(x)?,
+            Instruction::Pitch(x) => self.op_fx3a(x)?,
+            Instruction::LdIVx(x) => self.op_fx55(x)?,
+            Instruction::LdVxI(x) => self.op_fx65(x)?,
+            Instruction::LdRVx(x) => self.op_fx75(x)?,
+            Instruction::LdVxR(x) => self.op_fx85(x)?,
+            Instruction::Unknown(opcode) => return Err(CpuError::InvalidOpcode { pc: self.pc, opcode }),
         };
 
         match pc {
-            Some(value) => {
-                match value {
-                    ProgramCounter::Next => self.pc += 2,
-                    ProgramCounter::Skip => self.pc += 4,
-                    ProgramCounter::Jump(address) =>  self.pc = address ,
-                }
+            ProgramCounter::Next => self.pc += 2,
+            ProgramCounter::Skip => {
+                // Over the whole of the next instruction, F000 NNNN included.
+                self.pc += 2;
+                self.pc += self.get_opcode().map_or(2, |opcode| decode(opcode).size());
             }
-            None => {
-                eprintln!("Unknown opcode: {:#X?}.", opcode);
+            ProgramCounter::Jump(address) =>  self.pc = address ,
+            ProgramCounter::Exit => self.exited = true,
+        }
+        Ok(())
+    }
+
+    // 00Cn - SCD nibble (SUPER-CHIP)
+    // Scroll the display down by n pixels. Like all scroll instructions, only the
+    // selected bitplanes move.
+    fn op_00cn(&mut self, n: usize) -> Result<ProgramCounter, CpuError> {
+        let (width, height) = self.resolution();
+        let plane = self.plane;
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let src = if y >= n { self.vram[y - n][x] } else { 0 };
+                self.vram[y][x] = (self.vram[y][x] & !plane) | (src & plane);
             }
         }
+        self.redraw = true;
+        Ok(ProgramCounter::Next)
     }
 
     // 00E0 - CLS
-    fn op_00e0(&mut self) -> ProgramCounter {
-        for y in 0..CHIP8_GFX_HEIGHT {
-            for x in 0..CHIP8_GFX_WIDTH {
-                self.vram[y][x] = 0;
+    // Clears the selected bitplanes.
+    fn op_00e0(&mut self) -> Result<ProgramCounter, CpuError> {
+        for row in self.vram.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !self.plane;
             }
         }
         self.redraw = true;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
+
     // 00EE - RET
-    fn op_00ee(&mut self) -> ProgramCounter {
+    fn op_00ee(&mut self) -> Result<ProgramCounter, CpuError> {
+        if self.sp == 0 {
+            return Err(CpuError::StackUnderflow { pc: self.pc });
+        }
         self.sp -= 1;
         let address = self.stack[self.sp] + 2;
-        ProgramCounter::Jump(address)
+        Ok(ProgramCounter::Jump(address))
+    }
+
+    // 00FB - SCR (SUPER-CHIP)
+    // Scroll the display right by 4 pixels.
+    fn op_00fb(&mut self) -> Result<ProgramCounter, CpuError> {
+        let (width, height) = self.resolution();
+        let plane = self.plane;
+        for row in self.vram[..height].iter_mut() {
+            for x in (0..width).rev() {
+                let src = if x >= 4 { row[x - 4] } else { 0 };
+                row[x] = (row[x] & !plane) | (src & plane);
+            }
+        }
+        self.redraw = true;
+        Ok(ProgramCounter::Next)
+    }
+
+    // 00FC - SCL (SUPER-CHIP)
+    // Scroll the display left by 4 pixels.
+    fn op_00fc(&mut self) -> Result<ProgramCounter, CpuError> {
+        let (width, height) = self.resolution();
+        let plane = self.plane;
+        for row in self.vram[..height].iter_mut() {
+            for x in 0..width {
+                let src = if x + 4 < width { row[x + 4] } else { 0 };
+                row[x] = (row[x] & !plane) | (src & plane);
+            }
+        }
+        self.redraw = true;
+        Ok(ProgramCounter::Next)
+    }
+
+    // 00FD - EXIT (SUPER-CHIP)
+    // Exit the interpreter.
+    fn op_00fd(&mut self) -> Result<ProgramCounter, CpuError> {
+        Ok(ProgramCounter::Exit)
+    }
+
+    // 00FE - LOW (SUPER-CHIP)
+    // Switch to 64x32 low resolution mode. The display is cleared.
+    fn op_00fe(&mut self) -> Result<ProgramCounter, CpuError> {
+        self.hires = false;
+        self.clear_all_planes();
+        Ok(ProgramCounter::Next)
+    }
+
+    // 00FF - HIGH (SUPER-CHIP)
+    // Switch to 128x64 high resolution mode. The display is cleared.
+    fn op_00ff(&mut self) -> Result<ProgramCounter, CpuError> {
+        self.hires = true;
+        self.clear_all_planes();
+        Ok(ProgramCounter::Next)
+    }
+
+    fn clear_all_planes(&mut self) {
+        self.vram = [[0; SCHIP_GFX_WIDTH]; SCHIP_GFX_HEIGHT];
+        self.redraw = true;
     }
 
     // 1nnn - JP addr
-    fn op_1nnn(&mut self, nnn: usize) -> ProgramCounter {
-        ProgramCounter::Jump(nnn)
+    fn op_1nnn(&mut self, nnn: usize) -> Result<ProgramCounter, CpuError> {
+        Ok(ProgramCounter::Jump(nnn))
     }
 
     // 2nnn - CALL addr
-    fn op_2nnn(&mut self, nnn: usize) -> ProgramCounter {
+    fn op_2nnn(&mut self, nnn: usize) -> Result<ProgramCounter, CpuError> {
+        if self.sp == CHIP8_STACK_DEPTH {
+            return Err(CpuError::StackOverflow { pc: self.pc });
+        }
         self.stack[self.sp] = self.pc;
         self.sp += 1;
-        ProgramCounter::Jump(nnn)
+        Ok(ProgramCounter::Jump(nnn))
     }
 
     // 3xkk - SE Vx, byte
-    fn op_3xkk(&mut self, x: usize, kk: u8) -> ProgramCounter {
+    fn op_3xkk(&mut self, x: usize, kk: u8) -> Result<ProgramCounter, CpuError> {
         if self.v[x] == kk {
-            ProgramCounter::Skip
+            Ok(ProgramCounter::Skip)
         } else {
-            ProgramCounter::Next
+            Ok(ProgramCounter::Next)
         }
     }
 
     // 4xkk - SNE Vx, byte
-    fn op_4xkk(&mut self, x: usize, kk: u8) -> ProgramCounter {
+    fn op_4xkk(&mut self, x: usize, kk: u8) -> Result<ProgramCounter, CpuError> {
         if self.v[x] != kk {
-            ProgramCounter::Skip
+            Ok(ProgramCounter::Skip)
         } else {
-            ProgramCounter::Next
+            Ok(ProgramCounter::Next)
         }
      }
 
     // 5xy0 - SE Vx, Vy
-    fn op_5xy0(&mut self, x: usize, y: usize) -> ProgramCounter {
+    fn op_5xy0(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
         if self.v[x] == self.v[y] {
-            ProgramCounter::Skip
+            Ok(ProgramCounter::Skip)
         } else {
-            ProgramCounter::Next
+            Ok(ProgramCounter::Next)
         }
     }
 
+    // 5xy2 - LD [I], Vx-Vy (XO-CHIP)
+    // Store Vx through Vy in memory starting at I, in descending order if x > y.
+    // I is not changed.
+    fn op_5xy2(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
+        let registers = register_range(x, y);
+        self.check_range(self.i, registers.len())?;
+        for (offset, &r) in registers.iter().enumerate() {
+            self.write(self.i + offset, self.v[r])?;
+        }
+        Ok(ProgramCounter::Next)
+    }
+
+    // 5xy3 - LD Vx-Vy, [I] (XO-CHIP)
+    // Load Vx through Vy from memory starting at I, in descending order if x > y.
+    // I is not changed.
+    fn op_5xy3(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
+        let registers = register_range(x, y);
+        self.check_range(self.i, registers.len())?;
+        for (offset, &r) in registers.iter().enumerate() {
+            self.v[r] = self.read(self.i + offset)?;
+        }
+        Ok(ProgramCounter::Next)
+    }
+
     // 6xkk - LD Vx, byte
-    fn op_6xkk(&mut self, x: usize, kk: u8) -> ProgramCounter {
+    fn op_6xkk(&mut self, x: usize, kk: u8) -> Result<ProgramCounter, CpuError> {
         self.v[x] = kk;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 7xkk - ADD Vx, byte
-    fn op_7xkk(&mut self, x: usize, kk: u8) -> ProgramCounter {
+    fn op_7xkk(&mut self, x: usize, kk: u8) -> Result<ProgramCounter, CpuError> {
         self.v[x] = (self.v[x] as u16 + kk as u16) as u8;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy0 - LD Vx, Vy
-    fn op_8xy0(&mut self, x: usize, y: usize) -> ProgramCounter {
+    fn op_8xy0(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
         self.v[x] = self.v[y];
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy1 - OR Vx, Vy
-    fn op_8xy1(&mut self, x: usize, y: usize) -> ProgramCounter {
+    fn op_8xy1(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
         self.v[x] |= self.v[y];
-        ProgramCounter::Next
+        self.logic_vf_reset();
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy2 - AND Vx, Vy
-    fn op_8xy2(&mut self, x: usize, y: usize) -> ProgramCounter {
+    fn op_8xy2(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
         self.v[x] &= self.v[y];
-        ProgramCounter::Next
+        self.logic_vf_reset();
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy3 - XOR Vx, Vy
-    fn op_8xy3(&mut self, x: usize, y: usize) -> ProgramCounter {
+    fn op_8xy3(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
         self.v[x] ^= self.v[y];
-        ProgramCounter::Next
+        self.logic_vf_reset();
+        Ok(ProgramCounter::Next)
+    }
+
+    // The COSMAC VIP logic routines clobber VF as a side effect.
+    fn logic_vf_reset(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
+        }
     }
 
     // 8xy4 - ADD Vx, Vy
-    fn op_8xy4(&mut self, x: usize, y: usize) -> ProgramCounter {
+    fn op_8xy4(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
         let vx = self.v[x] as u16;
         let vy = self.v[y] as u16;
         let result = vx + vy;
         self.v[x] = result as u8;
         self.v[0xF] = if result > 0xFF { 1 } else { 0 };
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy5 - SUB Vx, Vy
-    fn op_8xy5(&mut self, x: usize, y: usize) -> ProgramCounter {
+    fn op_8xy5(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
         self.v[0xF] = if self.v[x] > self.v[y] { 1 } else { 0 };
         self.v[x] = self.v[x].wrapping_sub(self.v[y]);
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy6 - SHR Vx {, Vy}
-    fn op_8xy6(&mut self, x: usize) -> ProgramCounter {
-        self.v[0xF] = self.v[x] & 0x1;
-        self.v[x] >>= 1;
-        ProgramCounter::Next
+    // Set Vx = Vx SHR 1 (or Vy SHR 1, depending on quirks), VF = shifted out bit.
+    fn op_8xy6(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
+        let value = if self.quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+        self.v[x] = value >> 1;
+        self.v[0xF] = value & 0x1;
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy7 - SUBN Vx, Vy
     // Set Vx = Vy - Vx, set VF = NOT borrow.
     // If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results
     // stored in Vx.
-    fn op_8xy7(&mut self, x: usize, y: usize) -> ProgramCounter {
+    fn op_8xy7(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
         self.v[0xF] = if self.v[y] > self.v[x] { 1 } else { 0 };
         self.v[x] = self.v[y].wrapping_sub(self.v[x]);
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 8xyE - SHL Vx {, Vy}
-    // Set Vx = Vx SHL 1.
-    // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0.
-    // Then Vx is multiplied by 2.
-    fn op_8xye(&mut self, x: usize) -> ProgramCounter {
-        self.v[0xF] = (self.v[x] & 0b10000000) >> 7;
-        self.v[x] <<= 1;
-        ProgramCounter::Next
+    // Set Vx = Vx SHL 1 (or Vy SHL 1, depending on quirks).
+    // If the most-significant bit of the shifted value is 1, then VF is set to 1, otherwise to 0.
+    fn op_8xye(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
+        let value = if self.quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+        self.v[x] = value << 1;
+        self.v[0xF] = (value & 0b10000000) >> 7;
+        Ok(ProgramCounter::Next)
     }
 
     // 9xy0 - SNE Vx, Vy
     // Skip next instruction if Vx != Vy.
     // The values of Vx and Vy are compared, and if they are not equal,
     // the program counter is increased by 2.
-    fn op_9xy0(&mut self, x: usize, y: usize) -> ProgramCounter {
+    fn op_9xy0(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
         if self.v[x] != self.v[y] {
-            ProgramCounter::Skip
+            Ok(ProgramCounter::Skip)
         } else {
-            ProgramCounter::Next
+            Ok(ProgramCounter::Next)
         }
     }
 
     // Annn - LD I, addr
     // Set I = nnn.
     // The value of register I is set to nnn.
-    fn op_annn(&mut self, nnn: usize) -> ProgramCounter {
+    fn op_annn(&mut self, nnn: usize) -> Result<ProgramCounter, CpuError> {
         self.i = nnn;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Bnnn - JP V0, addr
     // Jump to location nnn + V0.
-    // The program counter is set to nnn plus the value of V0.
-    fn op_bnnn(&mut self, nnn: usize) -> ProgramCounter {
-        ProgramCounter::Jump(self.v[0x0] as usize + nnn)
+    // The program counter is set to nnn plus the value of V0. With the jump quirk the
+    // instruction is read as Bxnn instead, and jumps to xnn plus the value of Vx.
+    fn op_bnnn(&mut self, x: usize, nnn: usize) -> Result<ProgramCounter, CpuError> {
+        let offset = if self.quirks.jump_uses_vx { self.v[x] } else { self.v[0x0] };
+        Ok(ProgramCounter::Jump(offset as usize + nnn))
     }
 
     // Cxkk - RND Vx, byte
     // Set Vx = random byte AND kk.
     // The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk.
     // The results are stored in Vx.
-    fn op_cxkk(&mut self, x: usize, kk: u8) -> ProgramCounter {
-        let mut rng = rand::thread_rng();
-        self.v[x] = rng.gen::<u8>() & kk;
-        ProgramCounter::Next
+    fn op_cxkk(&mut self, x: usize, kk: u8) -> Result<ProgramCounter, CpuError> {
+        self.v[x] = self.rng.next_u8() & kk;
+        Ok(ProgramCounter::Next)
     }
 
     // Dxyn - DRW Vx, Vy, nibble
@@ -348,31 +1089,62 @@ impl CPU {
     // These bytes are then displayed as sprites on screen at coordinates (Vx, Vy).
     // Sprites are XORed onto the existing screen. If this causes any pixels to be erased,
     // VF is set to 1, otherwise it is set to 0. If the sprite is positioned so part of it is
-    // outside the coordinates of the display, it wraps around to the opposite side of the screen.
-    fn op_dxyn(&mut self, x: usize, y: usize, n: usize) -> ProgramCounter {
+    // outside the coordinates of the display, it wraps around to the opposite side of the screen,
+    // or is clipped if the clipping quirk is enabled.
+    // Dxy0 (SUPER-CHIP) draws a 16x16 sprite made of 32 bytes, two per row.
+    // With XO-CHIP bitplanes the sprite is drawn once per selected plane, the data for the
+    // second plane following the data for the first.
+    fn op_dxyn(&mut self, x: usize, y: usize, n: usize) -> Result<ProgramCounter, CpuError> {
+        let (width, height) = self.resolution();
+        let (rows, bytes_per_row) = if n == 0 { (16, 2) } else { (n, 1) };
+        let sprite_size = rows * bytes_per_row;
+        let x0 = self.v[x] as usize % width;
+        let y0 = self.v[y] as usize % height;
+        self.check_range(self.i, sprite_size * self.plane.count_ones() as usize)?;
         self.v[0xF] = 0;
-        for byte in 0..n {
-            let y = (self.v[y] as usize + byte) % CHIP8_GFX_HEIGHT;
-            for bit in 0..8 {
-                let x = (self.v[x] as usize + bit) % CHIP8_GFX_WIDTH;
-                let color = (self.ram[self.i + byte] >> (7 - bit)) & 1;
-                self.v[0xF] |= color & self.vram[y][x];
-                self.vram[y][x] ^= color;
+        let selected = self.plane;
+        let mut address = self.i;
+        for &plane in [0x1u8, 0x2].iter().filter(|&&plane| selected & plane != 0) {
+            for row in 0..rows {
+                let y = y0 + row;
+                if y >= height && self.quirks.clip_sprites {
+                    break;
+                }
+                let y = y % height;
+                let mut sprite = 0u16;
+                for byte in 0..bytes_per_row {
+                    sprite = sprite << 8 | self.read(address + row * bytes_per_row + byte)? as u16;
+                }
+                let sprite_width = bytes_per_row * 8;
+                for bit in 0..sprite_width {
+                    let x = x0 + bit;
+                    if x >= width && self.quirks.clip_sprites {
+                        break;
+                    }
+                    let x = x % width;
+                    if (sprite >> (sprite_width - 1 - bit)) & 1 == 1 {
+                        if self.vram[y][x] & plane != 0 {
+                            self.v[0xF] = 1;
+                        }
+                        self.vram[y][x] ^= plane;
+                    }
+                }
             }
+            address += sprite_size;
         }
         self.redraw = true;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Ex9E - SKP Vx
     // Skip next instruction if key with the value of Vx is pressed.
     // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the down
     // position, PC is increased by 2.
-    fn op_ex9e(&mut self, x: usize) -> ProgramCounter {
-        if self.keypad[self.v[x] as usize] {
-            ProgramCounter::Skip
+    fn op_ex9e(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        if self.keypad[(self.v[x] & 0xF) as usize] {
+            Ok(ProgramCounter::Skip)
         } else {
-            ProgramCounter::Next
+            Ok(ProgramCounter::Next)
         }
     }
 
@@ -380,116 +1152,178 @@ impl CPU {
     // Skip next instruction if key with the value of Vx is not pressed.
     // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up
     // position, PC is increased by 2.
-    fn op_exa1(&mut self, x: usize) -> ProgramCounter {
-        if !self.keypad[self.v[x] as usize] {
-            ProgramCounter::Skip
+    fn op_exa1(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        if !self.keypad[(self.v[x] & 0xF) as usize] {
+            Ok(ProgramCounter::Skip)
         } else {
-            ProgramCounter::Next
+            Ok(ProgramCounter::Next)
+        }
+    }
+
+    // F000 nnnn - LD I, long addr (XO-CHIP)
+    // Set I to the 16-bit address stored in the two bytes following the instruction.
+    fn op_f000(&mut self) -> Result<ProgramCounter, CpuError> {
+        self.i = (self.read(self.pc + 2)? as usize) << 8 | self.read(self.pc + 3)? as usize;
+        Ok(ProgramCounter::Jump(self.pc + 4))
+    }
+
+    // Fn01 - PLANE n (XO-CHIP)
+    // Select the bitplanes drawn, cleared and scrolled by subsequent instructions.
+    fn op_fn01(&mut self, n: usize) -> Result<ProgramCounter, CpuError> {
+        self.plane = (n & 0x3) as u8;
+        Ok(ProgramCounter::Next)
+    }
+
+    // F002 - AUDIO (XO-CHIP)
+    // Load the 16-byte audio pattern buffer from memory starting at I.
+    fn op_f002(&mut self) -> Result<ProgramCounter, CpuError> {
+        self.check_range(self.i, XOCHIP_AUDIO_PATTERN_SIZE)?;
+        let mut pattern = [0; XOCHIP_AUDIO_PATTERN_SIZE];
+        for (offset, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.read(self.i + offset)?;
         }
+        self.audio_pattern = Some(pattern);
+        Ok(ProgramCounter::Next)
     }
 
     // Fx07 - LD Vx, DT
     // Set Vx = delay timer value.
     // The value of DT is placed into Vx.
-    fn op_fx07(&mut self, x: usize) -> ProgramCounter {
+    fn op_fx07(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
         self.v[x] = self.delay_timer;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Fx0A - LD Vx, K
     // Wait for a key press, store the value of the key in Vx.
-    // All execution stops until a key is pressed, then the value of that key is stored in Vx.
-    fn op_fx0a(&mut self, x: usize) -> ProgramCounter {
+    // All execution stops until a key is pressed (and released, depending on the
+    // quirks), then the value of that key is stored in Vx. The timers keep running.
+    fn op_fx0a(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
         self.waiting_keypad = true;
         self.waiting_keypad_register = x;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Fx15 - LD DT, Vx
     // Set delay timer = Vx.
     // DT is set equal to the value of Vx.
-    fn op_fx15(&mut self, x: usize) -> ProgramCounter {
+    fn op_fx15(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
         self.delay_timer = self.v[x];
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Fx18 - LD ST, Vx
     // Set sound timer = Vx.
     // ST is set equal to the value of Vx.
-    fn op_fx18(&mut self, x: usize) -> ProgramCounter {
+    fn op_fx18(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
         self.sound_timer = self.v[x];
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Fx1E - ADD I, Vx
     // Set I = I + Vx.
-    // The values of I and Vx are added, and the results are stored in I.
-    fn op_fx1e(&mut self, x: usize) -> ProgramCounter {
+    // The values of I and Vx are added, and the results are stored in I. With the index
+    // overflow quirk, VF is also set to 1 if I ends up above 0xF00, and to 0 otherwise.
+    fn op_fx1e(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
         self.i += self.v[x] as usize;
-        self.v[0xF] = if self.i > 0x0F00 { 1 } else { 0 };
-        ProgramCounter::Next
+        if self.quirks.index_sets_vf {
+            self.v[0xF] = if self.i > 0x0F00 { 1 } else { 0 };
+        }
+        Ok(ProgramCounter::Next)
     }
 
     // Fx29 - LD F, Vx
     // Set I = location of sprite for digit Vx.
     // The value of I is set to the location for the hexadecimal sprite corresponding to the value
     // of Vx.
-    fn op_fx29(&mut self, x: usize) -> ProgramCounter {
-        self.i = (self.v[x] as usize) * 5;
-        ProgramCounter::Next
+    fn op_fx29(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.i = self.font_address + (self.v[x] & 0xF) as usize * FONT_SPRITE_SIZE;
+        Ok(ProgramCounter::Next)
+    }
+
+    // Fx30 - LD HF, Vx (SUPER-CHIP)
+    // Set I = location of the 8x10 big font sprite for digit Vx.
+    fn op_fx30(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.i = self.big_font_address + (self.v[x] & 0xF) as usize * BIG_FONT_SPRITE_SIZE;
+        Ok(ProgramCounter::Next)
     }
 
     // Fx33 - LD B, Vx
     // Store BCD representation of Vx in memory locations I, I+1, and I+2.
     // The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at
     // location in I, the tens digit at location I+1, and the ones digit at location I+2.
-    fn op_fx33(&mut self, x: usize) -> ProgramCounter {
-        self.ram[self.i] = self.v[x] / 100;
-        self.ram[self.i + 1] = (self.v[x] % 100) / 10;
-        self.ram[self.i + 2] = self.v[x] % 10;
-        ProgramCounter::Next
+    fn op_fx33(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.check_range(self.i, 3)?;
+        self.write(self.i, self.v[x] / 100)?;
+        self.write(self.i + 1, (self.v[x] % 100) / 10)?;
+        self.write(self.i + 2, self.v[x] % 10)?;
+        Ok(ProgramCounter::Next)
+    }
+
+    // Fx3A - PITCH Vx (XO-CHIP)
+    // Set the audio pattern playback rate to 4000 * 2^((Vx - 64) / 48) Hz.
+    fn op_fx3a(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.pitch = self.v[x];
+        Ok(ProgramCounter::Next)
     }
 
     // LD [I], Vx
     // The interpreter copies the values of registers V0 through Vx
     // into memory, starting at the address in I.
-    fn op_fx55(&mut self, x: usize) -> ProgramCounter {
+    fn op_fx55(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.check_range(self.i, x + 1)?;
         for i in 0..x + 1 {
-            self.ram[self.i + i] = self.v[i];
+            self.write(self.i + i, self.v[i])?;
         }
-        ProgramCounter::Next
+        self.increment_index(x);
+        Ok(ProgramCounter::Next)
     }
 
     // LD Vx, [I]
     // The interpreter reads values from memory starting at location
     // I into registers V0 through Vx.
-    fn op_fx65(&mut self, x: usize) -> ProgramCounter {
+    fn op_fx65(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.check_range(self.i, x + 1)?;
         for i in 0..x + 1 {
-            self.v[i] = self.ram[self.i + i];
+            self.v[i] = self.read(self.i + i)?;
+        }
+        self.increment_index(x);
+        Ok(ProgramCounter::Next)
+    }
+
+    // Fx75 - LD R, Vx (SUPER-CHIP)
+    // Store V0 through Vx in the RPL user flags.
+    fn op_fx75(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.rpl[..=x].copy_from_slice(&self.v[..=x]);
+        Ok(ProgramCounter::Next)
+    }
+
+    // Fx85 - LD Vx, R (SUPER-CHIP)
+    // Read V0 through Vx from the RPL user flags.
+    fn op_fx85(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.v[..=x].copy_from_slice(&self.rpl[..=x]);
+        Ok(ProgramCounter::Next)
+    }
+
+    // Applies the Fx55/Fx65 side effect on I selected by the quirks.
+    fn increment_index(&mut self, x: usize) {
+        match self.quirks.index_increment {
+            IndexIncrement::Unchanged => {}
+            IndexIncrement::ByX => self.i += x,
+            IndexIncrement::ByXPlusOne => self.i += x + 1,
         }
-        ProgramCounter::Next
     }
 }
 
 
-static FONT_SET: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-];
+// Registers x through y, in descending order if x > y.
+fn register_range(x: usize, y: usize) -> Vec<usize> {
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
 
 #[cfg(test)]
 #[path = "./cpu_tests.rs"]
@@ -1,6 +1,10 @@
 
 use std::io::{Write, Seek, SeekFrom};
 use crate::cpu::*;
+use crate::error::{CpuError, LoadError, StateError};
+use crate::font::*;
+use crate::quirks::Quirks;
+use crate::rng::{RandomSource, SplitMix64};
 
 // Random mini-program
 static HELLO_WORLD: [u8; 20] = [
@@ -15,66 +19,120 @@ fn load_hello_world(cpu: &mut CPU) {
     let _ = tmp_file.write(&HELLO_WORLD).unwrap();
     tmp_file.flush().unwrap();
     tmp_file.seek(SeekFrom::Start(0)).unwrap();
-    cpu.load_game(&mut tmp_file);
+    cpu.load_rom_from(&mut tmp_file).unwrap();
 }
 
 #[test]
 fn fontset_is_loaded_correctly() {
-    let cpu = CPU::new();
-    for i in 0..FONT_SET.len() {
-        assert_eq!(cpu.ram[0x50+i], FONT_SET[i]);
+    let cpu = CPU::new(Quirks::default());
+    for (i, byte) in Font::Chip8.sprites().iter().enumerate() {
+        assert_eq!(cpu.ram[0x50+i], *byte);
+    }
+    for (i, byte) in BIG_FONT.iter().enumerate() {
+        assert_eq!(cpu.ram[0xA0+i], *byte);
     }
 }
 
 #[test]
 fn game_is_loaded_correctly() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     load_hello_world(&mut cpu);
-    for i in 0..HELLO_WORLD.len() {
-        assert_eq!(cpu.ram[0x200+i], HELLO_WORLD[i]);
+    for (i, byte) in HELLO_WORLD.iter().enumerate() {
+        assert_eq!(cpu.ram[0x200+i], *byte);
     }
     assert_eq!(cpu.pc, 0x200);
 }
 
+#[test]
+fn oversize_rom_is_rejected() {
+    let mut cpu = CPU::new(Quirks::default());
+    let rom = vec![0xAA; 3585];
+    match cpu.load_rom(&rom) {
+        Err(LoadError::TooLarge { size: 3585, max: 3584 }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(cpu.ram[0x200], 0);
+    assert!(cpu.load_rom(&rom[..3584]).is_ok());
+    assert_eq!(cpu.ram[0xFFF], 0xAA);
+}
+
+#[test]
+fn rom_is_loaded_at_custom_address() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom_at(&HELLO_WORLD, ETI660_PROGRAM_START).unwrap();
+    assert_eq!(cpu.ram[0x600..0x600 + HELLO_WORLD.len()], HELLO_WORLD[..]);
+    assert_eq!(cpu.ram[0x200], 0);
+    assert_eq!(cpu.pc, 0x600);
+    match cpu.load_rom_at(&HELLO_WORLD, 0x100) {
+        Err(LoadError::InvalidAddress(0x100)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn loading_resets_machine_state() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_font(Font::Vip, 0x100).unwrap();
+    load_hello_world(&mut cpu);
+    cpu.v[0x3] = 0x42;
+    cpu.i = 0x123;
+    cpu.sp = 2;
+    cpu.delay_timer = 9;
+    cpu.vram[4][4] = 1;
+    cpu.ram[0x300] = 0xFF;
+    cpu.waiting_keypad = true;
+    cpu.load_rom(&[0x00, 0xE0]).unwrap();
+    assert_eq!(cpu.v[0x3], 0);
+    assert_eq!(cpu.i, 0);
+    assert_eq!(cpu.sp, 0);
+    assert_eq!(cpu.delay_timer, 0);
+    assert_eq!(cpu.vram[4][4], 0);
+    assert_eq!(cpu.ram[0x202], 0);
+    assert_eq!(cpu.ram[0x300], 0);
+    assert!(!cpu.waiting_keypad);
+    assert_eq!(cpu.font_address, 0x100);
+    assert_eq!(cpu.ram[0x100..0x100 + FONT_SIZE], Font::Vip.sprites()[..]);
+}
+
 #[test]
 fn opcode_is_read_correctly() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     load_hello_world(&mut cpu);
-    assert_eq!(0x6278, cpu.get_opcode());
+    assert_eq!(0x6278, cpu.get_opcode().unwrap());
 }
 
 #[test]
 fn op_00e0() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.vram[0][0] = 1;
-    cpu.exec_opcode(0x00E0);
+    cpu.exec_opcode(0x00E0).unwrap();
     assert_eq!(cpu.vram[0][0], 0);
     assert_eq!(cpu.pc, 0x202);
 }
 
 #[test]
 fn op_00ee() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     // Simulate CALL to 0x300 (0x2300)
     cpu.stack[cpu.sp] = cpu.pc;
     cpu.sp += 1;
     cpu.pc = 0x300;
-    cpu.exec_opcode(0x00EE);
+    cpu.exec_opcode(0x00EE).unwrap();
     assert_eq!(cpu.sp, 0);
     assert_eq!(cpu.pc, 0x202);
 }
 
 #[test]
 fn op_1nnn() {
-    let mut cpu = CPU::new();
-    cpu.exec_opcode(0x1333);
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.exec_opcode(0x1333).unwrap();
     assert_eq!(cpu.pc, 0x333);
 }
 
 #[test]
 fn op_2nnn() {
-    let mut cpu = CPU::new();
-    cpu.exec_opcode(0x2444);
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.exec_opcode(0x2444).unwrap();
     assert_eq!(cpu.sp, 1);
     assert_eq!(cpu.stack[cpu.sp-1], 0x200);
     assert_eq!(cpu.pc, 0x444);
@@ -82,61 +140,61 @@ fn op_2nnn() {
 
 #[test]
 fn op_3xkk() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x2] = 0x3;
-    cpu.exec_opcode(0x3203);
+    cpu.exec_opcode(0x3203).unwrap();
     assert_eq!(cpu.pc, 0x204);
-    cpu.exec_opcode(0x3204);
+    cpu.exec_opcode(0x3204).unwrap();
     assert_eq!(cpu.pc, 0x206);
 
 }
 
 #[test]
 fn op_4xkk() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0xA] = 0x5;
-    cpu.exec_opcode(0x4A07);
+    cpu.exec_opcode(0x4A07).unwrap();
     assert_eq!(cpu.pc, 0x204);
-    cpu.exec_opcode(0x4A05);
+    cpu.exec_opcode(0x4A05).unwrap();
     assert_eq!(cpu.pc, 0x206);
 
 }
 
 #[test]
 fn op_5xy0() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 1;
     cpu.v[0x1] = 1;
     cpu.v[0x2] = 2;
-    cpu.exec_opcode(0x5010);
+    cpu.exec_opcode(0x5010).unwrap();
     assert_eq!(cpu.pc, 0x204);
-    cpu.exec_opcode(0x5020);
+    cpu.exec_opcode(0x5020).unwrap();
     assert_eq!(cpu.pc, 0x206);
 }
 
 #[test]
 fn op_6xkk() {
-    let mut cpu = CPU::new();
-    cpu.exec_opcode(0x6A10);
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.exec_opcode(0x6A10).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0xA], 0x10);
 }
 
 #[test]
 fn op_7xkk() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x5] = 0x9;
-    cpu.exec_opcode(0x7501);
+    cpu.exec_opcode(0x7501).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0x5], 0xA);
 }
 
 #[test]
 fn op_8xy0() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x5] = 0x9;
     cpu.v[0xA] = 0xFF;
-    cpu.exec_opcode(0x85A0);
+    cpu.exec_opcode(0x85A0).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0x5], 0xFF);
     assert_eq!(cpu.v[0xA], 0xFF);
@@ -144,10 +202,10 @@ fn op_8xy0() {
 
 #[test]
 fn op_8xy1() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0x1;
     cpu.v[0x1] = 0x2;
-    cpu.exec_opcode(0x8011);
+    cpu.exec_opcode(0x8011).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0x0], 0x3);
     assert_eq!(cpu.v[0x1], 0x2);
@@ -155,10 +213,10 @@ fn op_8xy1() {
 
 #[test]
 fn op_8xy2() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0x1;
     cpu.v[0x1] = 0x2;
-    cpu.exec_opcode(0x8012);
+    cpu.exec_opcode(0x8012).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0x0], 0x0);
     assert_eq!(cpu.v[0x1], 0x2);
@@ -166,10 +224,10 @@ fn op_8xy2() {
 
 #[test]
 fn op_8xy3() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0x1;
     cpu.v[0x1] = 0x2;
-    cpu.exec_opcode(0x8013);
+    cpu.exec_opcode(0x8013).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0x0], 0x3);
     assert_eq!(cpu.v[0x1], 0x2);
@@ -177,10 +235,10 @@ fn op_8xy3() {
 
 #[test]
 fn op_8xy4() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0x10;
     cpu.v[0x1] = 0xFF;
-    cpu.exec_opcode(0x8014);
+    cpu.exec_opcode(0x8014).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0x0], ((0x10 + 0xFF) as u16) as u8);
     assert_eq!(cpu.v[0x1], 0xFF);
@@ -188,7 +246,7 @@ fn op_8xy4() {
     cpu.v[0x0] = 0x05;
     cpu.v[0x1] = 0xF0;
     cpu.v[0xF] = 0;
-    cpu.exec_opcode(0x8014);
+    cpu.exec_opcode(0x8014).unwrap();
     assert_eq!(cpu.pc, 0x204);
     assert_eq!(cpu.v[0x0], 0xF5);
     assert_eq!(cpu.v[0x1], 0xF0);
@@ -197,18 +255,18 @@ fn op_8xy4() {
 
 #[test]
 fn op_8xy5() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0xF;
     cpu.v[0x1] = 0x5;
-    cpu.exec_opcode(0x8015);
+    cpu.exec_opcode(0x8015).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0x0], 0xA);
     assert_eq!(cpu.v[0xF], 0x1);
     cpu.v[0x0] = 0x5;
-    let old_v0 = 0x5 as u8;
+    let old_v0: u8 = 0x5;
     cpu.v[0x1] = 0xF;
     cpu.v[0xF] = 0;
-    cpu.exec_opcode(0x8015);
+    cpu.exec_opcode(0x8015).unwrap();
     assert_eq!(cpu.pc, 0x204);
     assert_eq!(cpu.v[0x0], old_v0.wrapping_sub(cpu.v[0x1]));
     assert_eq!(cpu.v[0xF], 0x0);
@@ -216,32 +274,43 @@ fn op_8xy5() {
 
 #[test]
 fn op_8xy6() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0xA;
-    cpu.exec_opcode(0x80F6);
+    cpu.exec_opcode(0x80F6).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0x0], 0x5);
     assert_eq!(cpu.v[0xF], 0x0);
     cpu.v[0x0] = 0xFF;
-    cpu.exec_opcode(0x80D6);
+    cpu.exec_opcode(0x80D6).unwrap();
     assert_eq!(cpu.pc, 0x204);
     assert_eq!(cpu.v[0x0], 0x7F);
     assert_eq!(cpu.v[0xF], 0x1);
 }
 
+#[test]
+fn op_8xy6_shift_quirk() {
+    let mut cpu = CPU::new(Quirks::cosmac_vip());
+    cpu.v[0x0] = 0xFF;
+    cpu.v[0x1] = 0x5;
+    cpu.exec_opcode(0x8016).unwrap();
+    assert_eq!(cpu.v[0x0], 0x2);
+    assert_eq!(cpu.v[0x1], 0x5);
+    assert_eq!(cpu.v[0xF], 0x1);
+}
+
 #[test]
 fn op_8xy7() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0x0;
     cpu.v[0x1] = 0xA;
-    cpu.exec_opcode(0x8017);
+    cpu.exec_opcode(0x8017).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0x0], 0xA);
     assert_eq!(cpu.v[0xF], 0x1);
     cpu.v[0x0] = 0x1;
     cpu.v[0x1] = 0x0;
     cpu.v[0xF] = 0x0;
-    cpu.exec_opcode(0x8017);
+    cpu.exec_opcode(0x8017).unwrap();
     assert_eq!(cpu.pc, 0x204);
     assert_eq!(cpu.v[0x0], 0xFF);
     assert_eq!(cpu.v[0xF], 0x0);
@@ -249,52 +318,95 @@ fn op_8xy7() {
 
 #[test]
 fn op_8xye() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0xA;
-    cpu.exec_opcode(0x80EE);
+    cpu.exec_opcode(0x80EE).unwrap();
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.v[0x0], 0x14);
     assert_eq!(cpu.v[0xF], 0x0);
     cpu.v[0x0] = 0xFF;
     cpu.v[0xF] = 0x0;
-    cpu.exec_opcode(0x80FE);
+    cpu.exec_opcode(0x80FE).unwrap();
     assert_eq!(cpu.pc, 0x204);
     assert_eq!(cpu.v[0x0], 0xFE);
     assert_eq!(cpu.v[0xF], 0x1);
 }
 
+#[test]
+fn op_8xye_shift_quirk() {
+    let mut cpu = CPU::new(Quirks::cosmac_vip());
+    cpu.v[0x0] = 0x1;
+    cpu.v[0x1] = 0x81;
+    cpu.exec_opcode(0x801E).unwrap();
+    assert_eq!(cpu.v[0x0], 0x2);
+    assert_eq!(cpu.v[0x1], 0x81);
+    assert_eq!(cpu.v[0xF], 0x1);
+}
+
+#[test]
+fn shift_flag_overrides_vf_result() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.v[0xF] = 0x3;
+    cpu.exec_opcode(0x8F06).unwrap();
+    assert_eq!(cpu.v[0xF], 0x1);
+}
+
+#[test]
+fn op_8xy1_8xy2_8xy3_vf_reset_quirk() {
+    for opcode in [0x8011, 0x8012, 0x8013].iter() {
+        let mut cpu = CPU::new(Quirks::cosmac_vip());
+        cpu.v[0xF] = 0x1;
+        cpu.exec_opcode(*opcode).unwrap();
+        assert_eq!(cpu.v[0xF], 0x0);
+
+        let mut cpu = CPU::new(Quirks::schip());
+        cpu.v[0xF] = 0x1;
+        cpu.exec_opcode(*opcode).unwrap();
+        assert_eq!(cpu.v[0xF], 0x1);
+    }
+}
+
 #[test]
 fn op_9xy0() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0x0;
     cpu.v[0x1] = 0x1;
-    cpu.exec_opcode(0x9010);
+    cpu.exec_opcode(0x9010).unwrap();
     assert_eq!(cpu.pc, 0x204);
     cpu.v[0x0] = 0x1;
-    cpu.exec_opcode(0x9010);
+    cpu.exec_opcode(0x9010).unwrap();
     assert_eq!(cpu.pc, 0x206);
 }
 
 #[test]
 fn op_annn() {
-    let mut cpu = CPU::new();
-    cpu.exec_opcode(0xA123);
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.exec_opcode(0xA123).unwrap();
     assert_eq!(cpu.i, 0x123);
     assert_eq!(cpu.pc, 0x202);
 }
 
 #[test]
 fn op_bnnn() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0] = 5;
-    cpu.exec_opcode(0xB123);
+    cpu.exec_opcode(0xB123).unwrap();
     assert_eq!(cpu.pc, 0x128);
 }
 
+#[test]
+fn op_bnnn_jump_quirk() {
+    let mut cpu = CPU::new(Quirks::schip());
+    cpu.v[0] = 5;
+    cpu.v[1] = 7;
+    cpu.exec_opcode(0xB123).unwrap();
+    assert_eq!(cpu.pc, 0x12A);
+}
+
 #[test]
 fn op_dxyn() {
     // http://www.emulator101.com/chip-8-sprites.html
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     // Load space invader sprite at 0x500
     let space_invader_sprite: [u8; 6] = [
         /* X.XXX.X. */ 0b10111010,
@@ -304,9 +416,7 @@ fn op_dxyn() {
         /* .X.X.X.. */ 0b01010100,
         /* X.X.X.X. */ 0b10101010
     ];
-    for i in 0..space_invader_sprite.len() {
-        cpu.ram[0x500+i] = space_invader_sprite[i];
-    }
+    cpu.ram[0x500..0x500 + space_invader_sprite.len()].copy_from_slice(&space_invader_sprite);
 
     assert_eq!(cpu.ram[0x500], 0xBA);
     assert_eq!(cpu.ram[0x501], 0x7C);
@@ -319,7 +429,7 @@ fn op_dxyn() {
     cpu.v[0x0] = 0;
     cpu.v[0x1] = 0;
 
-    cpu.exec_opcode(0xD016);
+    cpu.exec_opcode(0xD016).unwrap();
 
     assert_eq!(cpu.vram[0][0], 1);
     assert_eq!(cpu.vram[0][1], 0);
@@ -379,91 +489,188 @@ fn op_dxyn() {
     assert_eq!(cpu.pc, 0x202);
 }
 
+#[test]
+fn op_dxyn_clips_at_screen_edge() {
+    let mut cpu = CPU::new(Quirks::schip());
+    cpu.ram[0x500] = 0xFF;
+    cpu.ram[0x501] = 0xFF;
+    cpu.i = 0x500;
+    cpu.v[0x0] = 60;
+    cpu.v[0x1] = 31;
+    cpu.exec_opcode(0xD012).unwrap();
+    assert_eq!(cpu.vram[31][60..64], [1, 1, 1, 1]);
+    assert_eq!(cpu.vram[31][0..4], [0, 0, 0, 0]);
+    assert_eq!(cpu.vram[0][60..64], [0, 0, 0, 0]);
+}
+
+#[test]
+fn op_dxyn_wraps_without_clip_quirk() {
+    let mut cpu = CPU::new(Quirks::xochip());
+    cpu.ram[0x500] = 0xFF;
+    cpu.ram[0x501] = 0xFF;
+    cpu.i = 0x500;
+    cpu.v[0x0] = 60;
+    cpu.v[0x1] = 31;
+    cpu.exec_opcode(0xD012).unwrap();
+    assert_eq!(cpu.vram[31][60..64], [1, 1, 1, 1]);
+    assert_eq!(cpu.vram[31][0..4], [1, 1, 1, 1]);
+    assert_eq!(cpu.vram[0][60..64], [1, 1, 1, 1]);
+    assert_eq!(cpu.vram[0][0..4], [1, 1, 1, 1]);
+}
+
+#[test]
+fn op_dxyn_wraps_start_position() {
+    let mut cpu = CPU::new(Quirks::schip());
+    cpu.ram[0x500] = 0x80;
+    cpu.i = 0x500;
+    cpu.v[0x0] = 65;
+    cpu.v[0x1] = 33;
+    cpu.exec_opcode(0xD011).unwrap();
+    assert_eq!(cpu.vram[1][1], 1);
+}
+
 #[test]
 fn op_ex9e() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0x0;
     cpu.keypad[0x0] = true;
-    cpu.exec_opcode(0xE09E);
+    cpu.exec_opcode(0xE09E).unwrap();
     assert_eq!(cpu.pc, 0x204);
     cpu.keypad[0x0] = false;
-    cpu.exec_opcode(0xE09E);
+    cpu.exec_opcode(0xE09E).unwrap();
     assert_eq!(cpu.pc, 0x206);
 }
 
 #[test]
 fn op_exa1() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0x0;
     cpu.keypad[0x0] = false;
-    cpu.exec_opcode(0xE0A1);
+    cpu.exec_opcode(0xE0A1).unwrap();
     assert_eq!(cpu.pc, 0x204);
     cpu.keypad[0x0] = true;
-    cpu.exec_opcode(0xE0A1);
+    cpu.exec_opcode(0xE0A1).unwrap();
     assert_eq!(cpu.pc, 0x206);
 }
 
 #[test]
 fn op_fx07() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.delay_timer = 0x5;
-    cpu.exec_opcode(0xF007);
+    cpu.exec_opcode(0xF007).unwrap();
     assert_eq!(cpu.v[0x0], 0x5);
     assert_eq!(cpu.pc, 0x202);
 }
 
 #[test]
 fn op_fx0a() {
-    let mut cpu = CPU::new();
-    cpu.exec_opcode(0xF00A);
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.exec_opcode(0xF00A).unwrap();
     assert_eq!(cpu.pc, 0x202);
-    assert_eq!(cpu.waiting_keypad, true);
+    assert!(cpu.waiting_keypad);
     assert_eq!(cpu.waiting_keypad_register, 0x0);
 }
 
 #[test]
 fn op_fx15() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0xA] = 0x5;
-    cpu.exec_opcode(0xFA15);
+    cpu.exec_opcode(0xFA15).unwrap();
     assert_eq!(cpu.delay_timer, 0x5);
     assert_eq!(cpu.pc, 0x202);
 }
 
 #[test]
 fn op_fx18() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0xA] = 0x5;
-    cpu.exec_opcode(0xFA18);
+    cpu.exec_opcode(0xFA18).unwrap();
     assert_eq!(cpu.sound_timer, 0x5);
     assert_eq!(cpu.pc, 0x202);
 }
 
 #[test]
 fn op_fx1e() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.i = 0x1;
     cpu.v[0x0] = 0x5;
-    cpu.exec_opcode(0xF01E);
+    cpu.exec_opcode(0xF01E).unwrap();
     assert_eq!(cpu.i, 0x6);
     assert_eq!(cpu.pc, 0x202);
 }
 
+#[test]
+fn op_fx1e_index_vf_quirk() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.i = 0xF00;
+    cpu.v[0x0] = 0x5;
+    cpu.exec_opcode(0xF01E).unwrap();
+    assert_eq!(cpu.v[0xF], 0x1);
+
+    let mut cpu = CPU::new(Quirks::xochip());
+    cpu.i = 0xF00;
+    cpu.v[0x0] = 0x5;
+    cpu.v[0xF] = 0x7;
+    cpu.exec_opcode(0xF01E).unwrap();
+    assert_eq!(cpu.i, 0xF05);
+    assert_eq!(cpu.v[0xF], 0x7);
+}
+
 #[test]
 fn op_fx29() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0x2;
-    cpu.exec_opcode(0xF029);
-    assert_eq!(cpu.i, 0xA);
+    cpu.exec_opcode(0xF029).unwrap();
+    assert_eq!(cpu.i, 0x5A);
     assert_eq!(cpu.pc, 0x202);
 }
 
+#[test]
+fn op_fx29_uses_font_address() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_font(Font::Vip, 0x100).unwrap();
+    cpu.v[0x0] = 0xF;
+    cpu.exec_opcode(0xF029).unwrap();
+    assert_eq!(cpu.i, 0x100 + 0xF * 5);
+    assert_eq!(cpu.ram[0x100..0x100 + FONT_SIZE], Font::Vip.sprites()[..]);
+}
+
+#[test]
+fn load_font_rejects_addresses_overlapping_the_program() {
+    let mut cpu = CPU::new(Quirks::default());
+    assert!(matches!(cpu.load_font(Font::Vip, 0x1F0), Err(LoadError::InvalidFontAddress(0x1F0))));
+    assert!(matches!(cpu.load_font(Font::Vip, usize::MAX), Err(LoadError::InvalidFontAddress(usize::MAX))));
+    assert_eq!(cpu.font_address(), DEFAULT_FONT_ADDRESS);
+}
+
+#[test]
+fn every_hex_digit_is_drawn() {
+    for &font in [Font::Chip8, Font::Vip, Font::Dream6800].iter() {
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.load_font(font, 0x000).unwrap();
+        for digit in 0..16u8 {
+            cpu.exec_opcode(0x00E0).unwrap();
+            cpu.v[0x0] = digit;
+            cpu.v[0x1] = 0;
+            cpu.exec_opcode(0xF029).unwrap();
+            cpu.exec_opcode(0xD115).unwrap();
+            let sprite = &font.sprites()[digit as usize * 5..digit as usize * 5 + 5];
+            for (row, bits) in sprite.iter().enumerate() {
+                for col in 0..8 {
+                    assert_eq!(cpu.vram[row][col], (bits >> (7 - col)) & 1,
+                               "{:?} digit {:X} row {} col {}", font, digit, row, col);
+                }
+            }
+        }
+    }
+}
+
 #[test]
 fn op_fx33() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.v[0x0] = 0xFF;
     cpu.i = 0x300;
-    cpu.exec_opcode(0xF033);
+    cpu.exec_opcode(0xF033).unwrap();
     assert_eq!(cpu.ram[cpu.i], 0x2);
     assert_eq!(cpu.ram[cpu.i+1], 0x5);
     assert_eq!(cpu.ram[cpu.i+2], 0x5);
@@ -472,12 +679,12 @@ fn op_fx33() {
 
 #[test]
 fn op_fx55() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.i = 0x300;
     cpu.v[0x0] = 0x1;
     cpu.v[0x1] = 0x2;
     cpu.v[0x2] = 0x4;
-    cpu.exec_opcode(0xF255);
+    cpu.exec_opcode(0xF255).unwrap();
     assert_eq!(cpu.ram[cpu.i], 0x1);
     assert_eq!(cpu.ram[cpu.i+1], 0x2);
     assert_eq!(cpu.ram[cpu.i+2], 0x4);
@@ -486,25 +693,550 @@ fn op_fx55() {
 
 #[test]
 fn op_fx65() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.i = 0x300;
     cpu.ram[0x300] = 0x1;
     cpu.ram[0x301] = 0x2;
     cpu.ram[0x302] = 0x4;
-    cpu.exec_opcode(0xF265);
+    cpu.exec_opcode(0xF265).unwrap();
     assert_eq!(cpu.v[0x0], 0x1);
     assert_eq!(cpu.v[0x1], 0x2);
     assert_eq!(cpu.v[0x2], 0x4);
     assert_eq!(cpu.pc, 0x202);
 }
 
+#[test]
+fn op_fx55_fx65_index_quirk() {
+    let mut cpu = CPU::new(Quirks::schip());
+    cpu.i = 0x300;
+    cpu.exec_opcode(0xF255).unwrap();
+    assert_eq!(cpu.i, 0x300);
+
+    let mut cpu = CPU::new(Quirks::chip48());
+    cpu.i = 0x300;
+    cpu.exec_opcode(0xF255).unwrap();
+    assert_eq!(cpu.i, 0x302);
+
+    let mut cpu = CPU::new(Quirks::cosmac_vip());
+    cpu.i = 0x300;
+    cpu.exec_opcode(0xF265).unwrap();
+    assert_eq!(cpu.i, 0x303);
+}
+
 #[test]
 fn timers() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     load_hello_world(&mut cpu);
     cpu.sound_timer = 10;
     cpu.delay_timer = 20;
-    cpu.tick([false; 16]);
+    cpu.tick_timers();
     assert_eq!(cpu.sound_timer, 9);
     assert_eq!(cpu.delay_timer, 19);
 }
+
+#[test]
+fn timers_are_not_decremented_by_instructions() {
+    let mut cpu = CPU::new(Quirks::default());
+    load_hello_world(&mut cpu);
+    cpu.sound_timer = 10;
+    cpu.delay_timer = 20;
+    for _ in 0..4 {
+        cpu.tick([false; 16]).unwrap();
+    }
+    assert_eq!(cpu.sound_timer, 10);
+    assert_eq!(cpu.delay_timer, 20);
+}
+
+#[test]
+fn run_frame() {
+    let mut cpu = CPU::new(Quirks::default());
+    load_hello_world(&mut cpu);
+    cpu.delay_timer = 20;
+    // LD V2, 0x78; LD I, 0x500; LD V3, 0x01; LD V4, 0x01
+    assert!(!cpu.run_frame(4).unwrap());
+    assert_eq!(cpu.pc, 0x208);
+    assert_eq!(cpu.delay_timer, 19);
+}
+
+#[test]
+fn stack_overflow() {
+    let mut cpu = CPU::new(Quirks::default());
+    for _ in 0..16 {
+        cpu.exec_opcode(0x2200).unwrap();
+    }
+    assert_eq!(cpu.exec_opcode(0x2200), Err(CpuError::StackOverflow { pc: 0x200 }));
+    assert_eq!(cpu.sp, 16);
+}
+
+#[test]
+fn stack_underflow() {
+    let mut cpu = CPU::new(Quirks::default());
+    assert_eq!(cpu.exec_opcode(0x00EE), Err(CpuError::StackUnderflow { pc: 0x200 }));
+    assert_eq!(cpu.sp, 0);
+    assert_eq!(cpu.pc, 0x200);
+}
+
+#[test]
+fn invalid_opcode() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.ram[0x200] = 0xFF;
+    cpu.ram[0x201] = 0xFF;
+    assert_eq!(cpu.step(), Err(CpuError::InvalidOpcode { pc: 0x200, opcode: 0xFFFF }));
+    assert_eq!(cpu.pc, 0x200);
+}
+
+#[test]
+fn fetch_out_of_bounds() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.pc = 0xFFF;
+    assert_eq!(cpu.step(), Err(CpuError::MemoryOutOfBounds { pc: 0xFFF, address: 0x1000 }));
+}
+
+#[test]
+fn memory_access_out_of_bounds() {
+    for &opcode in [0xD015, 0xF033, 0xF255, 0xF265].iter() {
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.i = 0xFFE;
+        cpu.v[0x0] = 0x7;
+        cpu.v[0x1] = 0x9;
+        let result = cpu.exec_opcode(opcode);
+        assert_eq!(result, Err(CpuError::MemoryOutOfBounds { pc: 0x200, address: 0x1000 }));
+        assert_eq!(cpu.ram[0xFFE..], [0, 0]);
+        assert_eq!(cpu.v[0x0], 0x7);
+        assert_eq!(cpu.v[0x1], 0x9);
+        assert_eq!(cpu.vram[0][0..8], [0; 8]);
+        assert_eq!(cpu.pc, 0x200);
+    }
+}
+
+#[test]
+fn huge_index_is_out_of_bounds() {
+    for &opcode in [0xD015, 0xF033, 0xF255, 0xF265].iter() {
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.i = usize::MAX;
+        let result = cpu.exec_opcode(opcode);
+        assert_eq!(result, Err(CpuError::MemoryOutOfBounds { pc: 0x200, address: usize::MAX }));
+    }
+}
+
+#[test]
+fn keypad_index_is_masked() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.v[0x0] = 0x1A;
+    cpu.keypad[0xA] = true;
+    cpu.exec_opcode(0xE09E).unwrap();
+    assert_eq!(cpu.pc, 0x204);
+}
+
+#[test]
+fn waiting_for_key_press() {
+    let mut cpu = CPU::new(Quirks { key_wait_release: false, ..Quirks::default() });
+    cpu.exec_opcode(0xF30A).unwrap();
+    assert_eq!(cpu.tick([false; 16]), Ok(StepOutcome::WaitingForKey));
+    let mut keypad = [false; 16];
+    keypad[0x7] = true;
+    assert_eq!(cpu.tick(keypad), Ok(StepOutcome::Executed));
+    assert_eq!(cpu.v[0x3], 0x7);
+    assert!(!cpu.waiting_keypad);
+}
+
+#[test]
+fn waiting_for_key_release() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.exec_opcode(0xF30A).unwrap();
+    let mut keypad = [false; 16];
+    keypad[0x7] = true;
+    assert_eq!(cpu.tick(keypad), Ok(StepOutcome::WaitingForKey));
+    // Other keys pressed meanwhile don't matter.
+    keypad[0x2] = true;
+    assert_eq!(cpu.tick(keypad), Ok(StepOutcome::WaitingForKey));
+    keypad[0x7] = false;
+    assert_eq!(cpu.tick(keypad), Ok(StepOutcome::Executed));
+    assert_eq!(cpu.v[0x3], 0x7);
+    assert!(!cpu.waiting_keypad);
+}
+
+#[test]
+fn held_key_satisfies_one_wait_only() {
+    // Two LD V0, K in a row.
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&[0xF0, 0x0A, 0xF1, 0x0A, 0x12, 0x04]).unwrap();
+    let mut keypad = [false; 16];
+    keypad[0x5] = true;
+    cpu.set_keypad(keypad);
+    cpu.run_frame(10).unwrap();
+    cpu.set_keypad([false; 16]);
+    cpu.run_frame(10).unwrap();
+    assert_eq!(cpu.v[0x0], 0x5);
+    assert_eq!(cpu.pc, 0x204);
+    assert!(cpu.waiting_keypad);
+
+    let mut cpu = CPU::new(Quirks { key_wait_release: false, ..Quirks::default() });
+    cpu.load_rom(&[0xF0, 0x0A, 0xF1, 0x0A, 0x12, 0x04]).unwrap();
+    cpu.set_keypad(keypad);
+    cpu.run_frame(10).unwrap();
+    assert_eq!(cpu.v[0x1], 0x5);
+    assert!(!cpu.waiting_keypad);
+}
+
+#[test]
+fn timers_run_while_waiting_for_key() {
+    for &release in &[true, false] {
+        let mut cpu = CPU::new(Quirks { key_wait_release: release, ..Quirks::default() });
+        cpu.load_rom(&[0xF0, 0x0A]).unwrap();
+        cpu.delay_timer = 10;
+        cpu.sound_timer = 5;
+        for _ in 0..3 {
+            cpu.run_frame(10).unwrap();
+        }
+        assert!(cpu.waiting_keypad);
+        assert_eq!(cpu.delay_timer, 7);
+        assert_eq!(cpu.sound_timer, 2);
+    }
+}
+
+#[test]
+fn op_00ff_00fe() {
+    let mut cpu = CPU::new(Quirks::default());
+    assert_eq!(cpu.resolution(), (64, 32));
+    cpu.vram[0][0] = 1;
+    cpu.exec_opcode(0x00FF).unwrap();
+    assert_eq!(cpu.resolution(), (128, 64));
+    assert_eq!(cpu.vram[0][0], 0);
+    assert_eq!(cpu.pc, 0x202);
+    cpu.exec_opcode(0x00FE).unwrap();
+    assert_eq!(cpu.resolution(), (64, 32));
+    assert_eq!(cpu.pc, 0x204);
+}
+
+#[test]
+fn op_00cn() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.vram[0][5] = 1;
+    cpu.vram[30][5] = 1;
+    cpu.exec_opcode(0x00C2).unwrap();
+    assert_eq!(cpu.vram[0][5], 0);
+    assert_eq!(cpu.vram[2][5], 1);
+    // Pixels scrolled past the bottom of the low resolution screen are lost.
+    assert_eq!(cpu.vram[30][5], 0);
+    assert_eq!(cpu.vram[32][5], 0);
+    assert_eq!(cpu.pc, 0x202);
+}
+
+#[test]
+fn op_00fb_00fc() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.exec_opcode(0x00FF).unwrap();
+    cpu.vram[3][0] = 1;
+    cpu.vram[3][126] = 1;
+    cpu.exec_opcode(0x00FB).unwrap();
+    assert_eq!(cpu.vram[3][0], 0);
+    assert_eq!(cpu.vram[3][4], 1);
+    assert_eq!(cpu.vram[3][126], 0);
+    cpu.exec_opcode(0x00FC).unwrap();
+    cpu.exec_opcode(0x00FC).unwrap();
+    assert_eq!(cpu.vram[3][0], 0);
+    assert_eq!(cpu.vram[3][4], 0);
+    assert_eq!(cpu.vram[3][124], 0);
+    assert_eq!(cpu.pc, 0x208);
+}
+
+#[test]
+fn op_00fd() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&[0x00, 0xFD, 0x60, 0x01]).unwrap();
+    assert_eq!(cpu.step(), Ok(StepOutcome::Exited));
+    assert!(cpu.exited());
+    assert_eq!(cpu.step(), Ok(StepOutcome::Exited));
+    assert_eq!(cpu.v[0x0], 0);
+    assert_eq!(cpu.pc, 0x200);
+}
+
+#[test]
+fn op_dxy0() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.exec_opcode(0x00FF).unwrap();
+    for i in 0..32 {
+        cpu.ram[0x500 + i] = if i % 2 == 0 { 0x80 } else { 0x01 };
+    }
+    cpu.i = 0x500;
+    cpu.v[0x0] = 100;
+    cpu.v[0x1] = 40;
+    cpu.exec_opcode(0xD010).unwrap();
+    for row in 0..16 {
+        assert_eq!(cpu.vram[40 + row][100], 1);
+        assert_eq!(cpu.vram[40 + row][115], 1);
+        assert_eq!(cpu.vram[40 + row][107], 0);
+    }
+    assert_eq!(cpu.vram[56][100], 0);
+    assert_eq!(cpu.v[0xF], 0);
+    cpu.exec_opcode(0xD010).unwrap();
+    assert_eq!(cpu.vram[40][100], 0);
+    assert_eq!(cpu.v[0xF], 1);
+}
+
+#[test]
+fn op_dxyn_hires_wraps_coordinates() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.exec_opcode(0x00FF).unwrap();
+    cpu.ram[0x500] = 0x80;
+    cpu.i = 0x500;
+    cpu.v[0x0] = 130;
+    cpu.v[0x1] = 70;
+    cpu.exec_opcode(0xD011).unwrap();
+    assert_eq!(cpu.vram[6][2], 1);
+}
+
+#[test]
+fn op_fx30() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.v[0x4] = 0x9;
+    cpu.exec_opcode(0xF430).unwrap();
+    assert_eq!(cpu.i, 0x50 + FONT_SIZE + 9 * 10);
+    assert_eq!(cpu.ram[cpu.i..cpu.i + 10], BIG_FONT[90..100]);
+    assert_eq!(cpu.pc, 0x202);
+}
+
+#[test]
+fn op_fx75_fx85() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.v[0x0] = 0x1;
+    cpu.v[0x1] = 0x2;
+    cpu.v[0x2] = 0x3;
+    cpu.exec_opcode(0xF175).unwrap();
+    cpu.v = [0; 16];
+    cpu.exec_opcode(0xF285).unwrap();
+    assert_eq!(cpu.v[0x0], 0x1);
+    assert_eq!(cpu.v[0x1], 0x2);
+    assert_eq!(cpu.v[0x2], 0x0);
+    assert_eq!(cpu.pc, 0x204);
+}
+
+#[test]
+fn xochip_memory_is_64k() {
+    let mut cpu = CPU::new(Quirks::xochip());
+    assert_eq!(cpu.ram.len(), 0x10000);
+    let rom = vec![0xAA; 0x10000 - 0x200];
+    cpu.load_rom(&rom).unwrap();
+    cpu.i = 0xFFFD;
+    cpu.v[0x0] = 0xFF;
+    cpu.exec_opcode(0xF033).unwrap();
+    assert_eq!(cpu.ram[0xFFFD..], [2, 5, 5]);
+}
+
+#[test]
+fn op_f000() {
+    let mut cpu = CPU::new(Quirks::xochip());
+    cpu.load_rom(&[0xF0, 0x00, 0xAB, 0xCD]).unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.i, 0xABCD);
+    assert_eq!(cpu.pc, 0x204);
+}
+
+#[test]
+fn skip_over_long_load() {
+    let mut cpu = CPU::new(Quirks::xochip());
+    cpu.load_rom(&[0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0x00, 0xE0]).unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 0x206);
+}
+
+#[test]
+fn op_5xy2_5xy3() {
+    let mut cpu = CPU::new(Quirks::xochip());
+    cpu.i = 0x300;
+    cpu.v[0x2] = 0x1;
+    cpu.v[0x3] = 0x2;
+    cpu.v[0x4] = 0x3;
+    cpu.exec_opcode(0x5242).unwrap();
+    assert_eq!(cpu.ram[0x300..0x303], [0x1, 0x2, 0x3]);
+    assert_eq!(cpu.i, 0x300);
+    cpu.exec_opcode(0x5422).unwrap();
+    assert_eq!(cpu.ram[0x300..0x303], [0x3, 0x2, 0x1]);
+    cpu.exec_opcode(0x5783).unwrap();
+    assert_eq!(cpu.v[0x7], 0x3);
+    assert_eq!(cpu.v[0x8], 0x2);
+    assert_eq!(cpu.i, 0x300);
+    assert_eq!(cpu.pc, 0x206);
+}
+
+#[test]
+fn op_fn01_draws_on_selected_planes() {
+    let mut cpu = CPU::new(Quirks::xochip());
+    cpu.ram[0x500] = 0x80;
+    cpu.ram[0x501] = 0xC0;
+    cpu.i = 0x500;
+    cpu.exec_opcode(0xF201).unwrap();
+    cpu.exec_opcode(0xD011).unwrap();
+    assert_eq!(cpu.vram[0][0], 0x2);
+    cpu.exec_opcode(0xF301).unwrap();
+    cpu.exec_opcode(0xD011).unwrap();
+    // Plane 1 uses the first byte, plane 2 the byte after it.
+    assert_eq!(cpu.vram[0][0], 0x1);
+    assert_eq!(cpu.vram[0][1], 0x2);
+    assert_eq!(cpu.v[0xF], 1);
+    cpu.exec_opcode(0xF101).unwrap();
+    cpu.exec_opcode(0x00E0).unwrap();
+    assert_eq!(cpu.vram[0][0], 0x0);
+    assert_eq!(cpu.vram[0][1], 0x2);
+}
+
+#[test]
+fn scroll_moves_selected_planes_only() {
+    let mut cpu = CPU::new(Quirks::xochip());
+    cpu.vram[0][0] = 0x3;
+    cpu.exec_opcode(0xF201).unwrap();
+    cpu.exec_opcode(0x00C1).unwrap();
+    assert_eq!(cpu.vram[0][0], 0x1);
+    assert_eq!(cpu.vram[1][0], 0x2);
+}
+
+#[test]
+fn op_f002_fx3a() {
+    let mut cpu = CPU::new(Quirks::xochip());
+    assert!(cpu.audio_pattern().is_none());
+    assert_eq!(cpu.audio_playback_rate(), 4000.0);
+    for i in 0..16 {
+        cpu.ram[0x400 + i] = i as u8;
+    }
+    cpu.i = 0x400;
+    cpu.exec_opcode(0xF002).unwrap();
+    assert_eq!(cpu.audio_pattern().unwrap()[15], 15);
+    cpu.v[0x1] = 112;
+    cpu.exec_opcode(0xF13A).unwrap();
+    assert_eq!(cpu.audio_playback_rate(), 8000.0);
+    assert_eq!(cpu.pc, 0x204);
+}
+
+#[test]
+fn save_and_load_state() {
+    let mut cpu = CPU::new(Quirks::default());
+    load_hello_world(&mut cpu);
+    cpu.run_frame(6).unwrap();
+    cpu.v[0xA] = 0x42;
+    cpu.i = 0x321;
+    cpu.stack[0] = 0x204;
+    cpu.sp = 1;
+    cpu.delay_timer = 7;
+    cpu.sound_timer = 3;
+    cpu.vram[10][20] = 1;
+    cpu.rpl[2] = 9;
+    cpu.waiting_keypad_key = Some(0xB);
+    let mut state = Vec::new();
+    cpu.save_state(&mut state).unwrap();
+
+    let mut restored = CPU::new(Quirks::default());
+    load_hello_world(&mut restored);
+    restored.load_state(&state[..]).unwrap();
+    assert_eq!(restored.ram, cpu.ram);
+    assert_eq!(restored.vram[..], cpu.vram[..]);
+    assert_eq!(restored.v, cpu.v);
+    assert_eq!(restored.i, cpu.i);
+    assert_eq!(restored.pc, cpu.pc);
+    assert_eq!(restored.stack, cpu.stack);
+    assert_eq!(restored.sp, cpu.sp);
+    assert_eq!(restored.delay_timer, cpu.delay_timer);
+    assert_eq!(restored.sound_timer, cpu.sound_timer);
+    assert_eq!(restored.waiting_keypad, cpu.waiting_keypad);
+    assert_eq!(restored.waiting_keypad_register, cpu.waiting_keypad_register);
+    assert_eq!(restored.waiting_keypad_key, cpu.waiting_keypad_key);
+    assert_eq!(restored.rpl, cpu.rpl);
+
+    let mut again = Vec::new();
+    restored.save_state(&mut again).unwrap();
+    assert_eq!(again, state);
+}
+
+#[test]
+fn state_from_other_rom_is_rejected() {
+    let mut cpu = CPU::new(Quirks::default());
+    load_hello_world(&mut cpu);
+    let mut state = Vec::new();
+    cpu.save_state(&mut state).unwrap();
+
+    let mut other = CPU::new(Quirks::default());
+    other.load_rom(&[0x12, 0x00]).unwrap();
+    match other.load_state(&state[..]) {
+        Err(StateError::RomMismatch { .. }) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert_eq!(other.ram[0x200], 0x12);
+}
+
+#[test]
+fn invalid_state_is_rejected() {
+    let mut cpu = CPU::new(Quirks::default());
+    load_hello_world(&mut cpu);
+    match cpu.load_state(&b"not a state"[..]) {
+        Err(StateError::NotAState) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    let mut state = Vec::new();
+    cpu.save_state(&mut state).unwrap();
+    state[4] = 0xFF;
+    match cpu.load_state(&state[..]) {
+        Err(StateError::UnsupportedVersion(_)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    state[4] = STATE_VERSION as u8;
+    state.truncate(100);
+    match cpu.load_state(&state[..]) {
+        Err(StateError::Io(_)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn op_cxkk_is_reproducible_with_seed() {
+    let mut a = CPU::new(Quirks::default());
+    let mut b = CPU::new(Quirks::default());
+    a.seed(1234);
+    b.seed(1234);
+    for _ in 0..32 {
+        a.exec_opcode(0xC0FF).unwrap();
+        b.exec_opcode(0xC0FF).unwrap();
+        assert_eq!(a.v[0x0], b.v[0x0]);
+    }
+}
+
+#[test]
+fn op_cxkk_masks_random_byte() {
+    struct Constant;
+    impl RandomSource for Constant {
+        fn next_u8(&mut self) -> u8 { 0xAB }
+        fn state(&self) -> u64 { 0 }
+        fn set_state(&mut self, _state: u64) {}
+    }
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.set_rng(Box::new(Constant));
+    cpu.exec_opcode(0xC30F).unwrap();
+    assert_eq!(cpu.v[0x3], 0x0B);
+    assert_eq!(cpu.pc, 0x202);
+}
+
+#[test]
+fn seed_survives_rom_loading() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.seed(99);
+    load_hello_world(&mut cpu);
+    let mut expected = SplitMix64::new(99);
+    cpu.exec_opcode(0xC0FF).unwrap();
+    assert_eq!(cpu.v[0x0], expected.next_u8());
+}
+
+#[test]
+fn rng_state_is_saved() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.seed(7);
+    load_hello_world(&mut cpu);
+    let mut state = Vec::new();
+    cpu.save_state(&mut state).unwrap();
+    cpu.exec_opcode(0xC0FF).unwrap();
+    let first = cpu.v[0x0];
+    cpu.seed(8);
+    cpu.load_state(&state[..]).unwrap();
+    cpu.exec_opcode(0xC0FF).unwrap();
+    assert_eq!(cpu.v[0x0], first);
+}
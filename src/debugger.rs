@@ -0,0 +1,553 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::cheat::{Cheat, CheatSearch, Comparison};
+use crate::cpu::{StepOutcome, CPU};
+use crate::error::CpuError;
+use crate::instruction::disassemble;
+use crate::watch::{Access, WatchHit, Watchpoint};
+
+// An interactive debugger driven by text commands. The frontend runs frames
+// through `Debugger::run_frame` and hands control to `Debugger::repl` when it
+// stops at a breakpoint or a fault.
+
+const PROMPT: &str = "(chip8) ";
+
+// Instructions listed before and after PC by `dis`.
+const CONTEXT_LINES: usize = 5;
+
+// Candidates listed by `search` once there are this few left.
+const LISTED_CANDIDATES: usize = 16;
+
+const HELP: &str = "\
+step [N]            execute N instructions (default 1)
+continue            resume execution
+break ADDR          stop before the instruction at ADDR
+break op PATTERN    stop before opcodes matching PATTERN, e.g. Dxyn or 00E0
+break               list breakpoints
+delete [ADDR|PATTERN]
+                    remove one breakpoint, or all of them
+regs                show registers, stack and timers
+mem ADDR [LEN]      dump LEN bytes of memory (default 64)
+poke TARGET VALUE   set a byte of memory (TARGET is an address) or a
+                    register (v0-vf, i, pc, dt, st)
+dis [ADDR]          disassemble around ADDR (default PC)
+watch TARGET [r|w|rw]
+                    stop after an instruction accesses TARGET: an address or
+                    range such as 0x300-0x30F (default w), or changes a
+                    register (v0-vf, i)
+watch               list watchpoints
+unwatch [TARGET]    remove one watchpoint, or all of them
+search              start a cheat search over all of memory
+search eq N|changed|unchanged|inc|dec
+                    keep the addresses whose value compares so to the
+                    previous search step
+freeze ADDR [VALUE] [NAME]
+                    hold ADDR at VALUE (default its current value) every frame
+unfreeze [ADDR]     remove one cheat, or all of them
+cheats              list cheats
+quit                stop the emulator
+";
+
+// Why execution stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    Breakpoint(usize),
+    Pattern { pc: usize, opcode: u16 },
+    // After the instruction that hit the watchpoints.
+    Watch(Vec<WatchHit>),
+    Fault(CpuError),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(pc) => write!(f, "breakpoint at {:#05X}", pc),
+            Stop::Pattern { pc, opcode } => write!(f, "opcode {:04X} at {:#05X}", opcode, pc),
+            Stop::Watch(hits) => {
+                let hits: Vec<String> = hits.iter().map(|hit| hit.to_string()).collect();
+                write!(f, "watchpoint: {}", hits.join("; "))
+            }
+            Stop::Fault(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// What the frontend should do when the REPL returns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    Continue,
+    Quit,
+}
+
+// An opcode pattern such as `Dxyn`: hex digits must match, anything else is
+// a wildcard.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodePattern {
+    mask: u16,
+    value: u16,
+}
+
+impl OpcodePattern {
+
+    pub fn parse(pattern: &str) -> Option<OpcodePattern> {
+        if pattern.chars().count() != 4 {
+            return None;
+        }
+        let (mut mask, mut value) = (0, 0);
+        for c in pattern.chars() {
+            mask <<= 4;
+            value <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                mask |= 0xF;
+                value |= digit as u16;
+            }
+        }
+        Some(OpcodePattern { mask, value })
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for shift in [12, 8, 4, 0].iter() {
+            if (self.mask >> shift) & 0xF == 0 {
+                write!(f, "?")?;
+            } else {
+                write!(f, "{:X}", (self.value >> shift) & 0xF)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: Vec<usize>,
+    patterns: Vec<OpcodePattern>,
+    // Set when resuming, so that the instruction execution stopped at does
+    // not stop it again.
+    resuming: bool,
+    search: Option<CheatSearch>,
+}
+
+impl Debugger {
+
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+
+    pub fn add_pattern(&mut self, pattern: OpcodePattern) {
+        if !self.patterns.contains(&pattern) {
+            self.patterns.push(pattern);
+        }
+    }
+
+    // Runs one frame like `CPU::run_frame`, but stops before any instruction
+    // with a breakpoint, and after any that hits a watchpoint. The timers only
+    // tick when the whole frame ran.
+    pub fn run_frame(&mut self, cpu: &mut CPU, instructions: u32) -> Result<bool, Stop> {
+        let mut redraw = false;
+        for _ in 0..instructions {
+            if !self.resuming {
+                if let Some(stop) = self.breakpoint(cpu) {
+                    return Err(stop);
+                }
+            }
+            self.resuming = false;
+            let outcome = cpu.step().map_err(Stop::Fault)?;
+            redraw |= cpu.redraw();
+            let hits = cpu.take_watch_hits();
+            if !hits.is_empty() {
+                return Err(Stop::Watch(hits));
+            }
+            if outcome == StepOutcome::Exited {
+                break;
+            }
+        }
+        cpu.end_frame();
+        Ok(redraw)
+    }
+
+    // The breakpoint that the next instruction hits, if any.
+    fn breakpoint(&self, cpu: &CPU) -> Option<Stop> {
+        if cpu.waiting_for_key() || cpu.exited() {
+            return None;
+        }
+        let pc = cpu.pc();
+        if self.breakpoints.contains(&pc) {
+            return Some(Stop::Breakpoint(pc));
+        }
+        let opcode = opcode_at(cpu, pc)?;
+        if self.patterns.iter().any(|pattern| pattern.matches(opcode)) {
+            return Some(Stop::Pattern { pc, opcode });
+        }
+        None
+    }
+
+    // Reads and runs commands until one resumes or ends execution. End of
+    // input counts as `quit`.
+    pub fn repl<R: BufRead, W: Write>(&mut self, cpu: &mut CPU, input: R, mut output: W) -> io::Result<Resume> {
+        self.show_next(cpu, &mut output)?;
+        write!(output, "{}", PROMPT)?;
+        output.flush()?;
+        for line in input.lines() {
+            if let Some(resume) = self.command(cpu, &line?, &mut output)? {
+                return Ok(resume);
+            }
+            write!(output, "{}", PROMPT)?;
+            output.flush()?;
+        }
+        writeln!(output)?;
+        Ok(Resume::Quit)
+    }
+
+    // Runs a single command. Returns how to resume, or None to stay in the REPL.
+    pub fn command<W: Write>(&mut self, cpu: &mut CPU, line: &str, output: &mut W) -> io::Result<Option<Resume>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (*name, args),
+            None => return Ok(None),
+        };
+        match (name, args) {
+            ("s", _) | ("step", _) => {
+                let count = match args.first() {
+                    Some(count) => match parse_number(count) {
+                        Some(count) => count,
+                        None => return usage(output, "step [N]"),
+                    },
+                    None => 1,
+                };
+                self.step(cpu, count, output)?;
+            }
+            ("c", []) | ("continue", []) => {
+                self.resuming = true;
+                return Ok(Some(Resume::Continue));
+            }
+            ("q", []) | ("quit", []) => return Ok(Some(Resume::Quit)),
+            ("b", []) | ("break", []) => {
+                for address in &self.breakpoints {
+                    writeln!(output, "  {:#05X}", address)?;
+                }
+                for pattern in &self.patterns {
+                    writeln!(output, "  op {}", pattern)?;
+                }
+            }
+            ("b", ["op", pattern]) | ("break", ["op", pattern]) => match OpcodePattern::parse(pattern) {
+                Some(pattern) => self.add_pattern(pattern),
+                None => return usage(output, "break op PATTERN"),
+            },
+            ("b", [address]) | ("break", [address]) => match parse_number(address) {
+                Some(address) => self.add_breakpoint(address),
+                None => return usage(output, "break ADDR"),
+            },
+            ("delete", []) => {
+                self.breakpoints.clear();
+                self.patterns.clear();
+            }
+            ("delete", [target]) => {
+                if let Some(address) = parse_number(target) {
+                    self.breakpoints.retain(|&breakpoint| breakpoint != address);
+                } else if let Some(pattern) = OpcodePattern::parse(target) {
+                    self.patterns.retain(|&other| other != pattern);
+                } else {
+                    return usage(output, "delete [ADDR|PATTERN]");
+                }
+            }
+            ("r", []) | ("regs", []) => show_registers(cpu, output)?,
+            ("m", _) | ("mem", _) => {
+                let address = args.first().and_then(|address| parse_number(address));
+                let len = match args.get(1) {
+                    Some(len) => parse_number(len),
+                    None => Some(64),
+                };
+                match (address, len) {
+                    (Some(address), Some(len)) if args.len() <= 2 => dump_memory(cpu, address, len, output)?,
+                    _ => return usage(output, "mem ADDR [LEN]"),
+                }
+            }
+            ("poke", [target, value]) => match parse_number(value) {
+                Some(value) => {
+                    if let Err(message) = poke(cpu, target, value) {
+                        writeln!(output, "{}", message)?;
+                    }
+                }
+                None => return usage(output, "poke TARGET VALUE"),
+            },
+            ("d", _) | ("dis", _) => match args {
+                [] => disassemble_around(cpu, cpu.pc(), output)?,
+                [address] => match parse_number(address) {
+                    Some(address) => disassemble_around(cpu, address, output)?,
+                    None => return usage(output, "dis [ADDR]"),
+                },
+                _ => return usage(output, "dis [ADDR]"),
+            },
+            ("w", []) | ("watch", []) => {
+                for watchpoint in cpu.watchpoints() {
+                    writeln!(output, "  {}", watchpoint)?;
+                }
+            }
+            ("w", [target]) | ("watch", [target]) => match parse_watchpoint(target, Access::Write) {
+                Some(watchpoint) if cpu.add_watchpoint(watchpoint) => {}
+                _ => return usage(output, "watch TARGET [r|w|rw]"),
+            },
+            ("w", [target, access]) | ("watch", [target, access]) => {
+                match Access::from_name(access).and_then(|access| parse_watchpoint(target, access)) {
+                    Some(watchpoint @ Watchpoint::Memory { .. }) => {
+                        cpu.add_watchpoint(watchpoint);
+                    }
+                    _ => return usage(output, "watch ADDR[-ADDR] [r|w|rw]"),
+                }
+            }
+            ("unwatch", []) => cpu.clear_watchpoints(),
+            ("unwatch", [target]) => match parse_watchpoint(target, Access::Write) {
+                Some(watchpoint) => {
+                    let matching: Vec<Watchpoint> = cpu
+                        .watchpoints()
+                        .iter()
+                        .filter(|other| same_target(other, &watchpoint))
+                        .cloned()
+                        .collect();
+                    if matching.is_empty() {
+                        writeln!(output, "no watchpoint on {}", target)?;
+                    }
+                    for watchpoint in &matching {
+                        cpu.remove_watchpoint(watchpoint);
+                    }
+                }
+                None => return usage(output, "unwatch [TARGET]"),
+            },
+            ("search", []) => {
+                let search = CheatSearch::new(cpu);
+                writeln!(output, "{} candidates", search.candidates().len())?;
+                self.search = Some(search);
+            }
+            ("search", _) => match parse_comparison(args) {
+                Some(comparison) => self.narrow_search(cpu, comparison, output)?,
+                None => return usage(output, "search [eq N|changed|unchanged|inc|dec]"),
+            },
+            ("freeze", [address, rest @ ..]) => {
+                let address = parse_number(address).filter(|&address| address < cpu.memory().len());
+                let value = match rest.first() {
+                    Some(value) => parse_number(value).filter(|&value| value <= 0xFF).map(|value| value as u8),
+                    None => address.map(|address| cpu.memory()[address]),
+                };
+                match (address, value) {
+                    (Some(address), Some(value)) => {
+                        let name = rest.get(1..).unwrap_or(&[]).join(" ");
+                        cpu.add_cheat(Cheat { address, value, name });
+                    }
+                    _ => return usage(output, "freeze ADDR [VALUE] [NAME]"),
+                }
+            }
+            ("unfreeze", []) => cpu.clear_cheats(),
+            ("unfreeze", [address]) => match parse_number(address) {
+                Some(address) => {
+                    if !cpu.remove_cheat(address) {
+                        writeln!(output, "no cheat on {:#05X}", address)?;
+                    }
+                }
+                None => return usage(output, "unfreeze [ADDR]"),
+            },
+            ("cheats", []) => {
+                for cheat in cpu.cheats() {
+                    let line = format!("  {:#05X} = {:#04X} {}", cheat.address, cheat.value, cheat.name);
+                    writeln!(output, "{}", line.trim_end())?;
+                }
+            }
+            ("h", []) | ("help", []) => write!(output, "{}", HELP)?,
+            _ => writeln!(output, "unknown command '{}', try 'help'", line.trim())?,
+        }
+        Ok(None)
+    }
+
+    // Executes up to `count` instructions, stopping early on faults, exits
+    // and Fx0A waiting for a key.
+    fn step<W: Write>(&mut self, cpu: &mut CPU, count: usize, output: &mut W) -> io::Result<()> {
+        for _ in 0..count {
+            let outcome = cpu.step();
+            let hits = cpu.take_watch_hits();
+            if !hits.is_empty() {
+                writeln!(output, "{}", Stop::Watch(hits))?;
+                break;
+            }
+            match outcome {
+                Ok(StepOutcome::Executed) => {}
+                Ok(StepOutcome::WaitingForKey) => {
+                    writeln!(output, "waiting for a key press")?;
+                    break;
+                }
+                Ok(StepOutcome::Exited) => {
+                    writeln!(output, "program exited")?;
+                    break;
+                }
+                Err(e) => {
+                    writeln!(output, "{}", e)?;
+                    break;
+                }
+            }
+        }
+        self.resuming = true;
+        self.show_next(cpu, output)
+    }
+
+    fn narrow_search<W: Write>(&mut self, cpu: &CPU, comparison: Comparison, output: &mut W) -> io::Result<()> {
+        let search = match self.search.as_mut() {
+            Some(search) => search,
+            None => return writeln!(output, "no search running, start one with 'search'"),
+        };
+        search.narrow(cpu, comparison);
+        let candidates = search.candidates();
+        writeln!(output, "{} candidates", candidates.len())?;
+        if candidates.len() <= LISTED_CANDIDATES {
+            for &address in candidates {
+                writeln!(output, "  {:#05X} = {:#04X}", address, cpu.memory()[address])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn show_next<W: Write>(&self, cpu: &CPU, output: &mut W) -> io::Result<()> {
+        let end = cpu.pc().saturating_add(4).min(cpu.memory().len());
+        match disassemble(&cpu.memory()[cpu.pc().min(end)..end], cpu.pc()).first() {
+            Some(line) => writeln!(output, "> {}", line),
+            None => writeln!(output, "> {:04X}  (out of memory)", cpu.pc()),
+        }
+    }
+}
+
+fn usage<W: Write>(output: &mut W, usage: &str) -> io::Result<Option<Resume>> {
+    writeln!(output, "usage: {}", usage)?;
+    Ok(None)
+}
+
+// A decimal or 0x-prefixed hexadecimal number.
+fn parse_number(value: &str) -> Option<usize> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        usize::from_str_radix(&value[2..], 16).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+fn parse_comparison(args: &[&str]) -> Option<Comparison> {
+    match args {
+        ["eq", value] => parse_number(value).filter(|&value| value <= 0xFF).map(|value| Comparison::Equal(value as u8)),
+        ["changed"] => Some(Comparison::Changed),
+        ["unchanged"] => Some(Comparison::Unchanged),
+        ["inc"] => Some(Comparison::Increased),
+        ["dec"] => Some(Comparison::Decreased),
+        _ => None,
+    }
+}
+
+// A register (v0-vf, i), an address or an inclusive address range.
+fn parse_watchpoint(target: &str, access: Access) -> Option<Watchpoint> {
+    let lower = target.to_ascii_lowercase();
+    if lower == "i" {
+        return Some(Watchpoint::Index);
+    }
+    if lower.len() == 2 && lower.starts_with('v') {
+        return usize::from_str_radix(&lower[1..], 16).ok().map(Watchpoint::Register);
+    }
+    let mut bounds = target.splitn(2, '-');
+    let start = parse_number(bounds.next()?)?;
+    let end = match bounds.next() {
+        Some(end) => parse_number(end)?,
+        None => start,
+    };
+    if start > end {
+        return None;
+    }
+    Some(Watchpoint::Memory { start, end, access })
+}
+
+// Whether two watchpoints watch the same thing, whatever the access.
+fn same_target(a: &Watchpoint, b: &Watchpoint) -> bool {
+    match (*a, *b) {
+        (Watchpoint::Memory { start, end, .. }, Watchpoint::Memory { start: other_start, end: other_end, .. }) => {
+            (start, end) == (other_start, other_end)
+        }
+        _ => a == b,
+    }
+}
+
+fn opcode_at(cpu: &CPU, address: usize) -> Option<u16> {
+    let memory = cpu.memory();
+    Some((*memory.get(address)? as u16) << 8 | *memory.get(address + 1)? as u16)
+}
+
+fn show_registers<W: Write>(cpu: &CPU, output: &mut W) -> io::Result<()> {
+    for (row, registers) in cpu.v().chunks(8).enumerate() {
+        for (column, value) in registers.iter().enumerate() {
+            write!(output, "V{:X}={:02X} ", row * 8 + column, value)?;
+        }
+        writeln!(output)?;
+    }
+    writeln!(output, "PC={:04X} I={:04X} SP={:X} DT={:02X} ST={:02X}",
+             cpu.pc(), cpu.i(), cpu.stack().len(), cpu.delay_timer(), cpu.sound_timer())?;
+    let stack: Vec<String> = cpu.stack().iter().map(|address| format!("{:04X}", address)).collect();
+    writeln!(output, "stack: [{}]", stack.join(" "))
+}
+
+fn dump_memory<W: Write>(cpu: &CPU, address: usize, len: usize, output: &mut W) -> io::Result<()> {
+    let memory = cpu.memory();
+    let end = address.saturating_add(len).min(memory.len());
+    for start in (address.min(end)..end).step_by(16) {
+        let row = &memory[start..(start + 16).min(end)];
+        let bytes: Vec<String> = row.iter().map(|byte| format!("{:02X}", byte)).collect();
+        writeln!(output, "{:04X}  {}", start, bytes.join(" "))?;
+    }
+    Ok(())
+}
+
+fn poke(cpu: &mut CPU, target: &str, value: usize) -> Result<(), String> {
+    let register = target.to_ascii_lowercase();
+    match register.as_str() {
+        "i" | "pc" if value >= cpu.memory().len() => return Err(format!("address {:#X} is out of memory", value)),
+        "i" => cpu.set_i(value),
+        "pc" => cpu.set_pc(value),
+        _ if value > 0xFF => return Err(format!("value {:#X} does not fit in a byte", value)),
+        "dt" => cpu.set_delay_timer(value as u8),
+        "st" => cpu.set_sound_timer(value as u8),
+        _ if register.len() == 2 && register.starts_with('v') => {
+            match usize::from_str_radix(&register[1..], 16) {
+                Ok(x) => cpu.set_v(x, value as u8),
+                Err(_) => return Err(format!("unknown register '{}'", target)),
+            }
+        }
+        _ => match parse_number(target) {
+            Some(address) if address < cpu.memory().len() => cpu.memory_mut()[address] = value as u8,
+            Some(address) => return Err(format!("address {:#X} is out of memory", address)),
+            None => return Err(format!("unknown target '{}'", target)),
+        },
+    }
+    Ok(())
+}
+
+// Lists the instructions before and after `address`, marking PC. Instructions
+// are decoded from a fixed distance before `address`, which may start in the
+// middle of an F000 long load.
+fn disassemble_around<W: Write>(cpu: &CPU, address: usize, output: &mut W) -> io::Result<()> {
+    let memory = cpu.memory();
+    let start = address.saturating_sub(CONTEXT_LINES * 2).min(memory.len());
+    let end = address.saturating_add(CONTEXT_LINES * 2 + 2).min(memory.len());
+    for line in disassemble(&memory[start..end], start) {
+        let marker = if line.address == cpu.pc() { ">" } else { " " };
+        writeln!(output, "{} {}", marker, line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "./debugger_tests.rs"]
+mod debugger_tests;
@@ -0,0 +1,182 @@
+use crate::cpu::CPU;
+use crate::debugger::*;
+use crate::error::CpuError;
+use crate::quirks::Quirks;
+use crate::watch::{Access, Target, WatchHit};
+
+// 0x200: V0 := 1, V1 := 2, V0 += V1, CLS, JP 0x200
+static PROGRAM: [u8; 10] = [0x60, 0x01, 0x61, 0x02, 0x80, 0x14, 0x00, 0xE0, 0x12, 0x00];
+
+fn program() -> CPU {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&PROGRAM).unwrap();
+    cpu
+}
+
+// Runs the REPL on `input` and returns how it ended and what it printed.
+fn repl(debugger: &mut Debugger, cpu: &mut CPU, input: &str) -> (Resume, String) {
+    let mut output = Vec::new();
+    let resume = debugger.repl(cpu, input.as_bytes(), &mut output).unwrap();
+    (resume, String::from_utf8(output).unwrap())
+}
+
+#[test]
+fn opcode_pattern() {
+    let pattern = OpcodePattern::parse("Dxyn").unwrap();
+    assert!(pattern.matches(0xD125));
+    assert!(!pattern.matches(0xC125));
+    assert_eq!(pattern.to_string(), "D???");
+    let pattern = OpcodePattern::parse("8??6").unwrap();
+    assert!(pattern.matches(0x8AB6));
+    assert!(!pattern.matches(0x8AB7));
+    assert!(OpcodePattern::parse("00E").is_none());
+}
+
+#[test]
+fn step_and_show_registers() {
+    let mut debugger = Debugger::new();
+    let mut cpu = program();
+    let (resume, output) = repl(&mut debugger, &mut cpu, "step 3\nregs\n");
+    assert_eq!(resume, Resume::Quit);
+    assert_eq!(cpu.pc(), 0x206);
+    assert_eq!(cpu.v()[0x0], 3);
+    assert!(output.starts_with("> 0200  60 01        LD V0, 0x01\n"));
+    assert!(output.contains("> 0206  00 E0        CLS\n"));
+    assert!(output.contains("V0=03 V1=02 "));
+    assert!(output.contains("PC=0206 I=0000 SP=0"));
+}
+
+#[test]
+fn run_frame_stops_at_breakpoints() {
+    let mut debugger = Debugger::new();
+    let mut cpu = program();
+    debugger.add_breakpoint(0x204);
+    assert_eq!(debugger.run_frame(&mut cpu, 10), Err(Stop::Breakpoint(0x204)));
+    assert_eq!(cpu.pc(), 0x204);
+
+    // Continuing runs the instruction it stopped at, and stops when it comes around again.
+    let (resume, _) = repl(&mut debugger, &mut cpu, "continue\n");
+    assert_eq!(resume, Resume::Continue);
+    assert_eq!(debugger.run_frame(&mut cpu, 1), Ok(false));
+    assert_eq!(cpu.v()[0x0], 3);
+    assert_eq!(debugger.run_frame(&mut cpu, 10), Err(Stop::Breakpoint(0x204)));
+}
+
+#[test]
+fn run_frame_stops_on_opcode_pattern() {
+    let mut debugger = Debugger::new();
+    let mut cpu = program();
+    let (_, output) = repl(&mut debugger, &mut cpu, "break op 00E0\nbreak 0x208\nbreak\n");
+    assert!(output.contains("  0x208\n  op 00E0\n"));
+    assert_eq!(debugger.run_frame(&mut cpu, 10), Err(Stop::Pattern { pc: 0x206, opcode: 0x00E0 }));
+
+    repl(&mut debugger, &mut cpu, "delete\ncontinue\n");
+    assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(true));
+}
+
+#[test]
+fn run_frame_reports_faults() {
+    let mut debugger = Debugger::new();
+    let mut cpu = program();
+    cpu.memory_mut()[0x200] = 0xFF;
+    cpu.memory_mut()[0x201] = 0xFF;
+    assert_eq!(debugger.run_frame(&mut cpu, 1),
+               Err(Stop::Fault(CpuError::InvalidOpcode { pc: 0x200, opcode: 0xFFFF })));
+}
+
+#[test]
+fn poke_memory_and_registers() {
+    let mut debugger = Debugger::new();
+    let mut cpu = program();
+    let (_, output) = repl(&mut debugger, &mut cpu, "poke 0x201 0x7F\npoke vA 12\npoke i 0x300\npoke dt 5\npoke 0x10000 1\n");
+    assert_eq!(cpu.memory()[0x201], 0x7F);
+    assert_eq!(cpu.v()[0xA], 12);
+    assert_eq!(cpu.i(), 0x300);
+    assert_eq!(cpu.delay_timer(), 5);
+    assert!(output.contains("address 0x10000 is out of memory"));
+
+    let (_, output) = repl(&mut debugger, &mut cpu, "poke v0 0x1FF\npoke dt 256\npoke 0x201 0x100\n");
+    assert_eq!(output.matches("does not fit in a byte").count(), 3);
+    assert_eq!(cpu.v()[0x0], 0);
+    assert_eq!(cpu.delay_timer(), 5);
+    assert_eq!(cpu.memory()[0x201], 0x7F);
+
+    let huge = "0xFFFFFFFFFFFFFFFF";
+    let input = format!("poke pc {0}\npoke i {0}\ndis {0}\nstep\n", huge);
+    let (_, output) = repl(&mut debugger, &mut cpu, &input);
+    assert_eq!(output.matches("address 0xFFFFFFFFFFFFFFFF is out of memory").count(), 2);
+    assert_eq!(cpu.i(), 0x300);
+
+    // The CPU API doesn't check, so the debugger can still be shown one.
+    cpu.set_pc(usize::MAX);
+    let (_, output) = repl(&mut debugger, &mut cpu, "dis\n");
+    assert!(output.starts_with("> FFFFFFFFFFFFFFFF  (out of memory)\n"));
+}
+
+#[test]
+fn dump_memory_and_disassemble() {
+    let mut debugger = Debugger::new();
+    let mut cpu = program();
+    let (_, output) = repl(&mut debugger, &mut cpu, "mem 0x200 20\ndis\n");
+    assert!(output.contains("0200  60 01 61 02 80 14 00 E0 12 00 00 00 00 00 00 00\n0210  00 00 00 00\n"));
+    assert!(output.contains("  01F6  00 00        DW 0x0000\n"));
+    assert!(output.contains("> 0200  60 01        LD V0, 0x01\n  0202  61 02        LD V1, 0x02\n"));
+}
+
+#[test]
+fn unknown_commands_and_usage() {
+    let mut debugger = Debugger::new();
+    let mut cpu = program();
+    let (_, output) = repl(&mut debugger, &mut cpu, "frobnicate\nbreak nowhere\n");
+    assert!(output.contains("unknown command 'frobnicate', try 'help'"));
+    assert!(output.contains("usage: break ADDR"));
+}
+
+#[test]
+fn watchpoints_from_the_repl() {
+    let mut debugger = Debugger::new();
+    let mut cpu = program();
+    let (_, output) = repl(&mut debugger, &mut cpu, "watch v0\nwatch 0x300-0x30F rw\nwatch\nstep 5\nstep 5\n");
+    assert!(output.contains("  V0\n  0x300-0x30F (read/write)\n"));
+    assert!(output.contains("watchpoint: write of V0: 0x00 -> 0x01 by LD V0, 0x01 at 0x200\n"));
+    assert!(output.contains("watchpoint: write of V0: 0x01 -> 0x03 by ADD V0, V1 at 0x204\n"));
+    assert_eq!(cpu.pc(), 0x206);
+
+    assert_eq!(debugger.run_frame(&mut cpu, 10), Err(Stop::Watch(vec![WatchHit {
+        pc: 0x200,
+        opcode: 0x6001,
+        target: Target::Register(0),
+        access: Access::Write,
+        old: 3,
+        new: 1,
+    }])));
+
+    repl(&mut debugger, &mut cpu, "unwatch 0x300-0x30F\nunwatch v0\n");
+    assert!(cpu.watchpoints().is_empty());
+    let (_, output) = repl(&mut debugger, &mut cpu, "unwatch vf\nwatch v0 r\n");
+    assert!(output.contains("no watchpoint on vf\n"));
+    assert!(output.contains("usage: watch ADDR[-ADDR] [r|w|rw]\n"));
+}
+
+#[test]
+fn cheats_from_the_repl() {
+    let mut debugger = Debugger::new();
+    let mut cpu = program();
+    let (_, output) = repl(&mut debugger, &mut cpu, "search inc\nsearch\npoke 0x300 5\nsearch changed\nsearch eq 300\n");
+    assert!(output.contains("no search running, start one with 'search'\n"));
+    assert!(output.contains("4096 candidates\n"));
+    assert!(output.contains("1 candidates\n  0x300 = 0x05\n"));
+    assert!(output.contains("usage: search [eq N|changed|unchanged|inc|dec]\n"));
+
+    let (_, output) = repl(&mut debugger, &mut cpu, "freeze 0x300 lives\nfreeze 0x300 9 extra lives\nfreeze 0x301\ncheats\n");
+    assert!(output.contains("usage: freeze ADDR [VALUE] [NAME]\n"));
+    assert!(output.contains("  0x300 = 0x09 extra lives\n  0x301 = 0x00\n"));
+    debugger.run_frame(&mut cpu, 10).unwrap();
+    assert_eq!(cpu.memory()[0x300], 9);
+
+    let (_, output) = repl(&mut debugger, &mut cpu, "unfreeze 0x301\nunfreeze 0x301\n");
+    assert!(output.contains("no cheat on 0x301\n"));
+    assert_eq!(cpu.cheats().len(), 1);
+    repl(&mut debugger, &mut cpu, "unfreeze\n");
+    assert!(cpu.cheats().is_empty());
+}
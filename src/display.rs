@@ -1,12 +1,13 @@
 
-use sdl2;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 use sdl2::pixels;
 use sdl2::rect::Rect;
 
-use crate::cpu::CHIP8_GFX_WIDTH;
-use crate::cpu::CHIP8_GFX_HEIGHT;
+use chip8::cpu::CHIP8_GFX_WIDTH;
+use chip8::cpu::CHIP8_GFX_HEIGHT;
+use chip8::image::PALETTE;
+use chip8::Framebuffer;
 
 const SCALE_FACTOR: u32 = 20;
 const SCREEN_WIDTH: u32 = (CHIP8_GFX_WIDTH as u32) * SCALE_FACTOR;
@@ -42,26 +43,26 @@ impl Display {
     }
 
 
-    pub fn draw(&mut self, pixels: &[[u8; CHIP8_GFX_WIDTH]; CHIP8_GFX_HEIGHT]) {
-        for (y, row) in pixels.iter().enumerate() {
-            for (x, &col) in row.iter().enumerate() {
-                let x = (x as u32) * SCALE_FACTOR;
-                let y = (y as u32) * SCALE_FACTOR;
+    // Draws the `width` x `height` area of the framebuffer, scaled to fill the window.
+    pub fn draw(&mut self, pixels: &Framebuffer, (width, height): (usize, usize)) {
+        let scale = SCREEN_WIDTH / width as u32;
+        for (y, row) in pixels[..height].iter().enumerate() {
+            for (x, &col) in row[..width].iter().enumerate() {
+                let x = (x as u32) * scale;
+                let y = (y as u32) * scale;
                 let color =self.color(col);
                 self.canvas.set_draw_color(color);
-                let rect = Rect::new(x as i32, y as i32, SCALE_FACTOR, SCALE_FACTOR);
+                let rect = Rect::new(x as i32, y as i32, scale, scale);
                 let _ = self.canvas.fill_rect(rect);
             }
         }
         self.canvas.present();
     }
 
+    // Pixels hold one bit per XO-CHIP bitplane, giving four colours.
     fn color(&mut self, value: u8) -> pixels::Color {
-        if value == 0 {
-            pixels::Color::RGB(0, 0, 0)
-        } else {
-            pixels::Color::RGB(200, 0, 0)
-        }
+        let (r, g, b) = PALETTE[(value & 0x3) as usize];
+        pixels::Color::RGB(r, g, b)
     }
 
 }
@@ -0,0 +1,280 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// A fault raised while executing an instruction. `pc` is the address of the
+// instruction that caused it; the machine state is left as it was before.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuError {
+    // CALL with all 16 stack entries in use.
+    StackOverflow { pc: usize },
+    // RET with an empty stack.
+    StackUnderflow { pc: usize },
+    // An opcode that is not part of the instruction set.
+    InvalidOpcode { pc: usize, opcode: u16 },
+    // A fetch, load or store outside of memory.
+    MemoryOutOfBounds { pc: usize, address: usize },
+}
+
+impl CpuError {
+
+    pub fn pc(&self) -> usize {
+        match *self {
+            CpuError::StackOverflow { pc } => pc,
+            CpuError::StackUnderflow { pc } => pc,
+            CpuError::InvalidOpcode { pc, .. } => pc,
+            CpuError::MemoryOutOfBounds { pc, .. } => pc,
+        }
+    }
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuError::StackOverflow { pc } => write!(f, "stack overflow at {:#05X}", pc),
+            CpuError::StackUnderflow { pc } => write!(f, "stack underflow at {:#05X}", pc),
+            CpuError::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode {:#06X} at {:#05X}", opcode, pc)
+            }
+            CpuError::MemoryOutOfBounds { pc, address } => {
+                write!(f, "memory access out of bounds ({:#X}) at {:#05X}", address, pc)
+            }
+        }
+    }
+}
+
+impl Error for CpuError {}
+
+// A ROM image that could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    // The image does not fit between the load address and the end of memory.
+    TooLarge { size: usize, max: usize },
+    // The load address is inside the interpreter area or past the end of memory.
+    InvalidAddress(usize),
+    // The fonts at this address would not fit below the program start address.
+    InvalidFontAddress(usize),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) => write!(f, "cannot read ROM: {}", e),
+            LoadError::TooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, but at most {} bytes fit in memory", size, max)
+            }
+            LoadError::InvalidAddress(address) => {
+                write!(f, "cannot load a ROM at {:#X}", address)
+            }
+            LoadError::InvalidFontAddress(address) => {
+                write!(f, "cannot load the font at {:#X}", address)
+            }
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            LoadError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+// A save state that could not be restored.
+#[derive(Debug)]
+pub enum StateError {
+    Io(io::Error),
+    // The data does not start with the save state signature.
+    NotAState,
+    // The state was written by an incompatible version of the emulator.
+    UnsupportedVersion(u16),
+    // The state was taken while running a different ROM.
+    RomMismatch { expected: u64, found: u64 },
+    // The state was taken on a machine with a different memory size.
+    MemorySizeMismatch { expected: usize, found: usize },
+    // A field holds a value the machine cannot be in.
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::Io(ref e) => write!(f, "cannot read state: {}", e),
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::RomMismatch { expected, found } => {
+                write!(f, "state is for ROM {:016x}, but ROM {:016x} is loaded", found, expected)
+            }
+            StateError::MemorySizeMismatch { expected, found } => {
+                write!(f, "state has {} bytes of memory, machine has {}", found, expected)
+            }
+            StateError::Corrupt(field) => write!(f, "corrupt save state ({})", field),
+        }
+    }
+}
+
+impl Error for StateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            StateError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StateError {
+    fn from(e: io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+// Octo source that could not be assembled. Lines and columns count from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+// A movie file that could not be read or played back.
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    // The data does not start with the movie signature.
+    NotAMovie,
+    // The movie was written by an incompatible version of the emulator.
+    UnsupportedVersion(u16),
+    // The movie was recorded with a different ROM.
+    RomMismatch { expected: u64, found: u64 },
+    // A field holds a value that cannot be played back.
+    Corrupt(&'static str),
+    // The ROM does not load with the recorded settings.
+    Load(LoadError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::Io(ref e) => write!(f, "cannot read movie: {}", e),
+            MovieError::NotAMovie => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "unsupported movie version {}", version)
+            }
+            MovieError::RomMismatch { expected, found } => {
+                write!(f, "movie is for ROM {:016x}, but ROM {:016x} is loaded", found, expected)
+            }
+            MovieError::Corrupt(field) => write!(f, "corrupt movie ({})", field),
+            MovieError::Load(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for MovieError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            MovieError::Io(ref e) => Some(e),
+            MovieError::Load(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MovieError {
+    fn from(e: io::Error) -> Self {
+        MovieError::Io(e)
+    }
+}
+
+impl From<LoadError> for MovieError {
+    fn from(e: LoadError) -> Self {
+        MovieError::Load(e)
+    }
+}
+
+// A cheat file that could not be read.
+#[derive(Debug)]
+pub enum CheatError {
+    Io(io::Error),
+    // A line that is not `ADDRESS VALUE [NAME]`, counting from 1.
+    Syntax(usize),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CheatError::Io(ref e) => write!(f, "cannot read cheats: {}", e),
+            CheatError::Syntax(line) => write!(f, "line {}: expected ADDRESS VALUE [NAME]", line),
+        }
+    }
+}
+
+impl Error for CheatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            CheatError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CheatError {
+    fn from(e: io::Error) -> Self {
+        CheatError::Io(e)
+    }
+}
+
+// A WAV file that could not be read.
+#[derive(Debug)]
+pub enum WavError {
+    Io(io::Error),
+    // Not a RIFF WAVE file, or one without a format or data chunk.
+    Malformed,
+    // An encoding other than 8, 16 or 32-bit PCM or 32-bit float.
+    Unsupported { format: u16, bits: u16 },
+}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WavError::Io(ref e) => write!(f, "cannot read WAV file: {}", e),
+            WavError::Malformed => write!(f, "not a WAV file"),
+            WavError::Unsupported { format, bits } => {
+                write!(f, "unsupported WAV encoding (format {}, {} bits)", format, bits)
+            }
+        }
+    }
+}
+
+impl Error for WavError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            WavError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WavError {
+    fn from(e: io::Error) -> Self {
+        WavError::Io(e)
+    }
+}
@@ -0,0 +1,161 @@
+use std::io::{self, Write};
+
+use crate::cpu::Framebuffer;
+use crate::state::fnv1a;
+
+// Writers for the visible part of a framebuffer, for screenshots and tests.
+// Pixels are colour indices: one bit per XO-CHIP bitplane.
+
+// RGB colour of each pixel value, shared with the SDL frontend.
+pub const PALETTE: [(u8, u8, u8); 4] = [
+    (0, 0, 0),
+    (200, 0, 0),
+    (0, 120, 200),
+    (230, 230, 230),
+];
+
+// Character for each pixel value in ASCII art.
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+// ANSI foreground colour of each pixel value for terminal output; adding 10
+// gives the background colour. Black, red, blue and bright white are as close
+// to the palette as the 16 standard colours get.
+const ANSI_COLOURS: [u8; 4] = [30, 31, 34, 97];
+
+// Largest block that deflate can store uncompressed.
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// Identifies a frame: a hash of the resolution and the visible pixels.
+pub fn frame_hash(vram: &Framebuffer, (width, height): (usize, usize)) -> u64 {
+    let mut data = vec![width as u8, height as u8];
+    for row in &vram[..height] {
+        data.extend_from_slice(&row[..width]);
+    }
+    fnv1a(&data)
+}
+
+// One line of text per row.
+pub fn write_ascii<W: Write>(vram: &Framebuffer, (width, height): (usize, usize), mut writer: W) -> io::Result<()> {
+    for row in &vram[..height] {
+        let line: String = row[..width].iter().map(|&pixel| ASCII_PIXELS[(pixel & 0x3) as usize]).collect();
+        writeln!(writer, "{}", line)?;
+    }
+    Ok(())
+}
+
+// Draws the framebuffer for an ANSI terminal, two pixel rows per line: every
+// cell is an upper half block in the colour of its top pixel, on a background
+// in the colour of the pixel below. Colour codes are only sent when they
+// change, and every line ends with the attributes reset.
+pub fn write_half_blocks<W: Write>(vram: &Framebuffer, (width, height): (usize, usize), mut writer: W) -> io::Result<()> {
+    let mut line = String::new();
+    for rows in vram[..height].chunks(2) {
+        line.clear();
+        let mut current = None;
+        for x in 0..width {
+            let top = rows[0][x] & 0x3;
+            let bottom = rows.get(1).map_or(0, |row| row[x] & 0x3);
+            if current != Some((top, bottom)) {
+                let fg = ANSI_COLOURS[top as usize];
+                let bg = ANSI_COLOURS[bottom as usize] + 10;
+                line.push_str(&format!("\x1b[{};{}m", fg, bg));
+                current = Some((top, bottom));
+            }
+            line.push('\u{2580}');
+        }
+        line.push_str("\x1b[0m\n");
+        writer.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
+
+// A binary (P4) portable bitmap. Any lit plane counts as black.
+pub fn write_pbm<W: Write>(vram: &Framebuffer, (width, height): (usize, usize), mut writer: W) -> io::Result<()> {
+    write!(writer, "P4\n{} {}\n", width, height)?;
+    for row in &vram[..height] {
+        let mut packed = vec![0u8; width.div_ceil(8)];
+        for (x, &pixel) in row[..width].iter().enumerate() {
+            if pixel != 0 {
+                packed[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        writer.write_all(&packed)?;
+    }
+    Ok(())
+}
+
+// An 8-bit paletted PNG in the frontend's colours. The image data is stored
+// without compression, which keeps the encoder tiny.
+pub fn write_png<W: Write>(vram: &Framebuffer, (width, height): (usize, usize), mut writer: W) -> io::Result<()> {
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per pixel, palette colour, deflate, no filtering, no interlacing.
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    write_chunk(&mut writer, b"IHDR", &header)?;
+
+    let palette: Vec<u8> = PALETTE.iter().flat_map(|&(r, g, b)| vec![r, g, b]).collect();
+    write_chunk(&mut writer, b"PLTE", &palette)?;
+
+    let mut scanlines = Vec::new();
+    for row in &vram[..height] {
+        // Filter type 0 (none), then one byte per pixel.
+        scanlines.push(0);
+        scanlines.extend(row[..width].iter().map(|&pixel| pixel & 0x3));
+    }
+    write_chunk(&mut writer, b"IDAT", &zlib_stored(&scanlines))?;
+    write_chunk(&mut writer, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let mut crc_data = kind.to_vec();
+    crc_data.extend_from_slice(data);
+    writer.write_all(&crc32(&crc_data).to_be_bytes())
+}
+
+// A zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8);
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+#[path = "./image_tests.rs"]
+mod image_tests;
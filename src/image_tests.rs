@@ -0,0 +1,58 @@
+use crate::cpu::Framebuffer;
+use crate::image::*;
+
+fn framebuffer() -> Framebuffer {
+    let mut vram = [[0; 128]; 64];
+    vram[0][0] = 1;
+    vram[0][9] = 1;
+    vram[1][1] = 2;
+    vram[2][2] = 3;
+    vram
+}
+
+#[test]
+fn ascii_art() {
+    let mut out = Vec::new();
+    write_ascii(&framebuffer(), (10, 3), &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "#........#\n.+........\n..@.......\n");
+}
+
+#[test]
+fn half_blocks() {
+    let mut out = Vec::new();
+    write_half_blocks(&framebuffer(), (3, 3), &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\x1b[31;40m\u{2580}\x1b[30;44m\u{2580}\x1b[30;40m\u{2580}\x1b[0m\n\
+         \x1b[30;40m\u{2580}\u{2580}\x1b[97;40m\u{2580}\x1b[0m\n"
+    );
+}
+
+#[test]
+fn pbm_bitmap() {
+    let mut out = Vec::new();
+    write_pbm(&framebuffer(), (10, 3), &mut out).unwrap();
+    assert_eq!(out, b"P4\n10 3\n\x80\x40\x40\x00\x20\x00".to_vec());
+}
+
+#[test]
+fn png_structure() {
+    let mut out = Vec::new();
+    write_png(&framebuffer(), (64, 32), &mut out).unwrap();
+    assert!(out.starts_with(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR\x00\x00\x00\x40\x00\x00\x00\x20\x08\x03"));
+    assert!(out.ends_with(b"\x00\x00\x00\x00IEND\xae\x42\x60\x82"));
+    // Signature, IHDR, PLTE, IDAT with 32 rows of 65 bytes, IEND.
+    assert_eq!(out.len(), 8 + 25 + 24 + (12 + 2 + 5 + 32 * 65 + 4) + 12);
+}
+
+#[test]
+fn frame_hash_covers_visible_pixels() {
+    let vram = framebuffer();
+    let hash = frame_hash(&vram, (64, 32));
+    let mut other = vram;
+    other[40][100] = 1;
+    assert_eq!(frame_hash(&other, (64, 32)), hash);
+    assert_ne!(frame_hash(&other, (128, 64)), frame_hash(&vram, (128, 64)));
+    other[0][0] = 0;
+    assert_ne!(frame_hash(&other, (64, 32)), hash);
+}
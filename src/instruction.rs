@@ -0,0 +1,337 @@
+use std::fmt;
+
+// A decoded instruction with its operands. Mnemonics follow Cowgod's
+// technical reference, extended for SUPER-CHIP and XO-CHIP.
+//
+// Register operands are register numbers (0x0-0xF), not register values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 00Cn
+    Scd(usize),
+    // 00E0
+    Cls,
+    // 00EE
+    Ret,
+    // 00FB
+    Scr,
+    // 00FC
+    Scl,
+    // 00FD
+    Exit,
+    // 00FE
+    Low,
+    // 00FF
+    High,
+    // 1nnn
+    Jp(usize),
+    // 2nnn
+    Call(usize),
+    // 3xkk
+    SeByte { x: usize, kk: u8 },
+    // 4xkk
+    SneByte { x: usize, kk: u8 },
+    // 5xy0
+    SeReg { x: usize, y: usize },
+    // 5xy2
+    SaveRange { x: usize, y: usize },
+    // 5xy3
+    LoadRange { x: usize, y: usize },
+    // 6xkk
+    LdByte { x: usize, kk: u8 },
+    // 7xkk
+    AddByte { x: usize, kk: u8 },
+    // 8xy0
+    LdReg { x: usize, y: usize },
+    // 8xy1
+    Or { x: usize, y: usize },
+    // 8xy2
+    And { x: usize, y: usize },
+    // 8xy3
+    Xor { x: usize, y: usize },
+    // 8xy4
+    AddReg { x: usize, y: usize },
+    // 8xy5
+    Sub { x: usize, y: usize },
+    // 8xy6
+    Shr { x: usize, y: usize },
+    // 8xy7
+    Subn { x: usize, y: usize },
+    // 8xyE
+    Shl { x: usize, y: usize },
+    // 9xy0
+    SneReg { x: usize, y: usize },
+    // Annn
+    LdI(usize),
+    // Bnnn. With the jump quirk the high nibble of nnn also names the register.
+    JpV0(usize),
+    // Cxkk
+    Rnd { x: usize, kk: u8 },
+    // Dxyn
+    Drw { x: usize, y: usize, n: usize },
+    // Ex9E
+    Skp(usize),
+    // ExA1
+    Sknp(usize),
+    // F000 nnnn. The address is in the word following the opcode.
+    LdLong,
+    // Fn01
+    Plane(usize),
+    // F002
+    Audio,
+    // Fx07
+    LdVxDt(usize),
+    // Fx0A
+    LdVxK(usize),
+    // Fx15
+    LdDtVx(usize),
+    // Fx18
+    LdStVx(usize),
+    // Fx1E
+    AddI(usize),
+    // Fx29
+    LdF(usize),
+    // Fx30
+    LdHf(usize),
+    // Fx33
+    LdB(usize),
+    // Fx3A
+    Pitch(usize),
+    // Fx55
+    LdIVx(usize),
+    // Fx65
+    LdVxI(usize),
+    // Fx75
+    LdRVx(usize),
+    // Fx85
+    LdVxR(usize),
+    // Anything else.
+    Unknown(u16),
+}
+
+pub fn decode(opcode: u16) -> Instruction {
+
+    let nibbles = (
+        ((opcode & 0xF000) >> 12) as u8,
+        ((opcode & 0x0F00) >> 8) as u8,
+        ((opcode & 0x00F0) >> 4) as u8,
+        (opcode & 0x000F) as u8,
+    );
+
+    let nnn = (opcode & 0x0FFF) as usize;
+    let kk = (opcode & 0x00FF) as u8;
+    let x = nibbles.1 as usize;
+    let y = nibbles.2 as usize;
+    let n = nibbles.3 as usize;
+
+    match nibbles {
+        (0x0, 0x0, 0xC, _  ) => Instruction::Scd(n),
+        (0x0, 0x0, 0xE, 0x0) => Instruction::Cls,
+        (0x0, 0x0, 0xE, 0xE) => Instruction::Ret,
+        (0x0, 0x0, 0xF, 0xB) => Instruction::Scr,
+        (0x0, 0x0, 0xF, 0xC) => Instruction::Scl,
+        (0x0, 0x0, 0xF, 0xD) => Instruction::Exit,
+        (0x0, 0x0, 0xF, 0xE) => Instruction::Low,
+        (0x0, 0x0, 0xF, 0xF) => Instruction::High,
+        (0x1, _,   _,   _  ) => Instruction::Jp(nnn),
+        (0x2, _,   _,   _  ) => Instruction::Call(nnn),
+        (0x3, _,   _,   _  ) => Instruction::SeByte { x, kk },
+        (0x4, _,   _,   _  ) => Instruction::SneByte { x, kk },
+        (0x5, _,   _,   0x0) => Instruction::SeReg { x, y },
+        (0x5, _,   _,   0x2) => Instruction::SaveRange { x, y },
+        (0x5, _,   _,   0x3) => Instruction::LoadRange { x, y },
+        (0x6, _,   _,   _  ) => Instruction::LdByte { x, kk },
+        (0x7, _,   _,   _  ) => Instruction::AddByte { x, kk },
+        (0x8, _,   _,   0x0) => Instruction::LdReg { x, y },
+        (0x8, _,   _,   0x1) => Instruction::Or { x, y },
+        (0x8, _,   _,   0x2) => Instruction::And { x, y },
+        (0x8, _,   _,   0x3) => Instruction::Xor { x, y },
+        (0x8, _,   _,   0x4) => Instruction::AddReg { x, y },
+        (0x8, _,   _,   0x5) => Instruction::Sub { x, y },
+        (0x8, _,   _,   0x6) => Instruction::Shr { x, y },
+        (0x8, _,   _,   0x7) => Instruction::Subn { x, y },
+        (0x8, _,   _,   0xE) => Instruction::Shl { x, y },
+        (0x9, _,   _,   0x0) => Instruction::SneReg { x, y },
+        (0xA, _,   _,   _  ) => Instruction::LdI(nnn),
+        (0xB, _,   _,   _  ) => Instruction::JpV0(nnn),
+        (0xC, _,   _,   _  ) => Instruction::Rnd { x, kk },
+        (0xD, _,   _,   _  ) => Instruction::Drw { x, y, n },
+        (0xE, _,   0x9, 0xE) => Instruction::Skp(x),
+        (0xE, _,   0xA, 0x1) => Instruction::Sknp(x),
+        (0xF, 0x0, 0x0, 0x0) => Instruction::LdLong,
+        (0xF, _,   0x0, 0x1) => Instruction::Plane(x),
+        (0xF, 0x0, 0x0, 0x2) => Instruction::Audio,
+        (0xF, _,   0x0, 0x7) => Instruction::LdVxDt(x),
+        (0xF, _,   0x0, 0xA) => Instruction::LdVxK(x),
+        (0xF, _,   0x1, 0x5) => Instruction::LdDtVx(x),
+        (0xF, _,   0x1, 0x8) => Instruction::LdStVx(x),
+        (0xF, _,   0x1, 0xE) => Instruction::AddI(x),
+        (0xF, _,   0x2, 0x9) => Instruction::LdF(x),
+        (0xF, _,   0x3, 0x0) => Instruction::LdHf(x),
+        (0xF, _,   0x3, 0x3) => Instruction::LdB(x),
+        (0xF, _,   0x3, 0xA) => Instruction::Pitch(x),
+        (0xF, _,   0x5, 0x5) => Instruction::LdIVx(x),
+        (0xF, _,   0x6, 0x5) => Instruction::LdVxI(x),
+        (0xF, _,   0x7, 0x5) => Instruction::LdRVx(x),
+        (0xF, _,   0x8, 0x5) => Instruction::LdVxR(x),
+        _ => Instruction::Unknown(opcode),
+    }
+}
+
+impl Instruction {
+
+    // Size in bytes of the instruction, including the operand word of F000.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LdLong => 4,
+            _ => 2,
+        }
+    }
+
+    // The V registers the instruction may read or write, in order. VF is
+    // included wherever it can receive a flag, whatever the quirks.
+    pub fn registers(&self) -> Vec<usize> {
+        let mut registers = match *self {
+            Instruction::SeByte { x, .. }
+            | Instruction::SneByte { x, .. }
+            | Instruction::LdByte { x, .. }
+            | Instruction::AddByte { x, .. }
+            | Instruction::Rnd { x, .. }
+            | Instruction::Skp(x)
+            | Instruction::Sknp(x)
+            | Instruction::LdVxDt(x)
+            | Instruction::LdVxK(x)
+            | Instruction::LdDtVx(x)
+            | Instruction::LdStVx(x)
+            | Instruction::LdF(x)
+            | Instruction::LdHf(x)
+            | Instruction::LdB(x)
+            | Instruction::Pitch(x) => vec![x],
+            Instruction::SeReg { x, y } | Instruction::SneReg { x, y } | Instruction::LdReg { x, y } => vec![x, y],
+            Instruction::Or { x, y }
+            | Instruction::And { x, y }
+            | Instruction::Xor { x, y }
+            | Instruction::AddReg { x, y }
+            | Instruction::Sub { x, y }
+            | Instruction::Shr { x, y }
+            | Instruction::Subn { x, y }
+            | Instruction::Shl { x, y }
+            | Instruction::Drw { x, y, .. } => vec![x, y, 0xF],
+            // VF flags I going past 0xF00 with the index overflow quirk.
+            Instruction::AddI(x) => vec![x, 0xF],
+            Instruction::SaveRange { x, y } | Instruction::LoadRange { x, y } => (x.min(y)..=x.max(y)).collect(),
+            Instruction::LdIVx(x) | Instruction::LdVxI(x) | Instruction::LdRVx(x) | Instruction::LdVxR(x) => {
+                (0..=x).collect()
+            }
+            Instruction::JpV0(nnn) => vec![0, nnn >> 8],
+            _ => Vec::new(),
+        };
+        registers.sort_unstable();
+        registers.dedup();
+        registers
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Scd(n) => write!(f, "SCD {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Scr => write!(f, "SCR"),
+            Instruction::Scl => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(nnn) => write!(f, "JP {:#05X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SeByte { x, kk } => write!(f, "SE V{:X}, {:#04X}", x, kk),
+            Instruction::SneByte { x, kk } => write!(f, "SNE V{:X}, {:#04X}", x, kk),
+            Instruction::SeReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            Instruction::LdByte { x, kk } => write!(f, "LD V{:X}, {:#04X}", x, kk),
+            Instruction::AddByte { x, kk } => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+            Instruction::LdReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JpV0(nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::Rnd { x, kk } => write!(f, "RND V{:X}, {:#04X}", x, kk),
+            Instruction::Drw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LdLong => write!(f, "LD I, long"),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdF(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHf(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdB(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LdVxR(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+// One line of a disassembly listing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassembledLine {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    // None for a trailing odd byte, which cannot hold an instruction.
+    pub instruction: Option<Instruction>,
+}
+
+impl fmt::Display for DisassembledLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        write!(f, "{:04X}  {:<11}  ", self.address, bytes.join(" "))?;
+        match self.instruction {
+            Some(Instruction::LdLong) if self.bytes.len() == 4 => {
+                write!(f, "LD I, {:#06X}", (self.bytes[2] as u16) << 8 | self.bytes[3] as u16)
+            }
+            Some(instruction) => write!(f, "{}", instruction),
+            None => write!(f, "DB {:#04X}", self.bytes[0]),
+        }
+    }
+}
+
+// Disassembles `code` linearly, as if it were loaded at `origin`. Data mixed
+// in with the code is decoded as instructions too.
+pub fn disassemble(code: &[u8], origin: usize) -> Vec<DisassembledLine> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let address = origin + offset;
+        if offset + 1 == code.len() {
+            lines.push(DisassembledLine { address, bytes: vec![code[offset]], instruction: None });
+            break;
+        }
+        let instruction = decode((code[offset] as u16) << 8 | code[offset + 1] as u16);
+        // A long load cut off by the end of the code is listed without its address.
+        let size = if offset + instruction.size() <= code.len() { instruction.size() } else { 2 };
+        let bytes = code[offset..offset + size].to_vec();
+        lines.push(DisassembledLine { address, bytes, instruction: Some(instruction) });
+        offset += size;
+    }
+    lines
+}
+
+#[cfg(test)]
+#[path = "./instruction_tests.rs"]
+mod instruction_tests;
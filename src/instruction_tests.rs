@@ -0,0 +1,80 @@
+use crate::instruction::*;
+
+#[test]
+fn decode_extracts_operands() {
+    assert_eq!(decode(0x00E0), Instruction::Cls);
+    assert_eq!(decode(0x00C4), Instruction::Scd(4));
+    assert_eq!(decode(0x1234), Instruction::Jp(0x234));
+    assert_eq!(decode(0x2ABC), Instruction::Call(0xABC));
+    assert_eq!(decode(0x3A42), Instruction::SeByte { x: 0xA, kk: 0x42 });
+    assert_eq!(decode(0x5123), Instruction::LoadRange { x: 0x1, y: 0x2 });
+    assert_eq!(decode(0x8ABE), Instruction::Shl { x: 0xA, y: 0xB });
+    assert_eq!(decode(0xB300), Instruction::JpV0(0x300));
+    assert_eq!(decode(0xD125), Instruction::Drw { x: 0x1, y: 0x2, n: 5 });
+    assert_eq!(decode(0xE59E), Instruction::Skp(0x5));
+    assert_eq!(decode(0xF000), Instruction::LdLong);
+    assert_eq!(decode(0xF201), Instruction::Plane(2));
+    assert_eq!(decode(0xF765), Instruction::LdVxI(0x7));
+}
+
+#[test]
+fn decode_rejects_unknown_opcodes() {
+    for &opcode in &[0x0000, 0x00E1, 0x5001, 0x800F, 0x9001, 0xE000, 0xF0FF, 0xF100] {
+        assert_eq!(decode(opcode), Instruction::Unknown(opcode));
+    }
+}
+
+#[test]
+fn display_uses_cowgod_mnemonics() {
+    assert_eq!(decode(0x00EE).to_string(), "RET");
+    assert_eq!(decode(0x1228).to_string(), "JP 0x228");
+    assert_eq!(decode(0x6A0F).to_string(), "LD VA, 0x0F");
+    assert_eq!(decode(0x8126).to_string(), "SHR V1, V2");
+    assert_eq!(decode(0xA22A).to_string(), "LD I, 0x22A");
+    assert_eq!(decode(0xB400).to_string(), "JP V0, 0x400");
+    assert_eq!(decode(0xC3FF).to_string(), "RND V3, 0xFF");
+    assert_eq!(decode(0xD01F).to_string(), "DRW V0, V1, 15");
+    assert_eq!(decode(0xF30A).to_string(), "LD V3, K");
+    assert_eq!(decode(0xF233).to_string(), "LD B, V2");
+    assert_eq!(decode(0xF455).to_string(), "LD [I], V4");
+    assert_eq!(decode(0x5262).to_string(), "LD [I], V2-V6");
+    assert_eq!(decode(0xFFFF).to_string(), "DW 0xFFFF");
+}
+
+#[test]
+fn disassemble_lists_whole_rom() {
+    let rom = [0x00, 0xE0, 0xF0, 0x00, 0x12, 0x34, 0xA2, 0x00, 0xFF];
+    let lines = disassemble(&rom, 0x200);
+    let addresses: Vec<usize> = lines.iter().map(|line| line.address).collect();
+    assert_eq!(addresses, vec![0x200, 0x202, 0x206, 0x208]);
+    assert_eq!(lines[1].bytes, vec![0xF0, 0x00, 0x12, 0x34]);
+    assert_eq!(lines[3].instruction, None);
+
+    let listing: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    assert_eq!(listing, vec![
+        "0200  00 E0        CLS",
+        "0202  F0 00 12 34  LD I, 0x1234",
+        "0206  A2 00        LD I, 0x200",
+        "0208  FF           DB 0xFF",
+    ]);
+}
+
+#[test]
+fn disassemble_truncated_long_load() {
+    let lines = disassemble(&[0xF0, 0x00, 0x12], 0x200);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].to_string(), "0200  F0 00        LD I, long");
+    assert_eq!(lines[1].to_string(), "0202  12           DB 0x12");
+}
+
+#[test]
+fn registers_touched() {
+    assert_eq!(decode(0x00E0).registers(), vec![]);
+    assert_eq!(decode(0x6A12).registers(), vec![0xA]);
+    assert_eq!(decode(0x8124).registers(), vec![0x1, 0x2, 0xF]);
+    assert_eq!(decode(0x8FF6).registers(), vec![0xF]);
+    assert_eq!(decode(0x5632).registers(), vec![0x3, 0x4, 0x5, 0x6]);
+    assert_eq!(decode(0xF265).registers(), vec![0x0, 0x1, 0x2]);
+    assert_eq!(decode(0xB300).registers(), vec![0x0, 0x3]);
+    assert_eq!(decode(0xF31E).registers(), vec![0x3, 0xF]);
+}
@@ -1,24 +1,43 @@
 
-use sdl2;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
+
+// Emulator commands bound to function keys. F1..F4 select save state slots 1-4.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hotkey {
+    // Shift + F1..F4
+    SaveState(u8),
+    // F1..F4
+    LoadState(u8),
+    // F5
+    Mute,
+    // F6 lowers the volume by a step, F7 raises it.
+    Volume(i32),
+}
 
 pub struct Keyboard {
     events: sdl2::EventPump,
+    hotkeys: Vec<Hotkey>,
 }
 
 impl Keyboard {
 
     pub fn new(sdl_context: &sdl2::Sdl) -> Self {
-        Keyboard { events: sdl_context.event_pump().unwrap() }
+        Keyboard { events: sdl_context.event_pump().unwrap(), hotkeys: Vec::new() }
     }
 
     pub fn poll(&mut self) -> Result<[bool; 16], ()> {
 
         for event in self.events.poll_iter() {
-            if let Event::Quit { .. } = event {
-                return Err(());
-            };
+            match event {
+                Event::Quit { .. } => return Err(()),
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } => {
+                    if let Some(hotkey) = hotkey(keycode, keymod) {
+                        self.hotkeys.push(hotkey);
+                    }
+                }
+                _ => {}
+            }
         }
 
         let keys: Vec<Keycode> = self.events
@@ -57,4 +76,45 @@ impl Keyboard {
 
         Ok(chip8_keys)
     }
-}
\ No newline at end of file
+
+    // Hotkeys pressed since the last call.
+    pub fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        self.hotkeys.drain(..).collect()
+    }
+
+    // Fast-forward is active while Tab is held.
+    pub fn turbo(&self) -> bool {
+        self.held(Keycode::Tab)
+    }
+
+    // The game runs backwards while Backspace is held.
+    pub fn rewinding(&self) -> bool {
+        self.held(Keycode::Backspace)
+    }
+
+    fn held(&self, keycode: Keycode) -> bool {
+        self.events
+            .keyboard_state()
+            .pressed_scancodes()
+            .filter_map(Keycode::from_scancode)
+            .any(|key| key == keycode)
+    }
+}
+
+fn hotkey(keycode: Keycode, keymod: Mod) -> Option<Hotkey> {
+    let slot = match keycode {
+        Keycode::F5 => return Some(Hotkey::Mute),
+        Keycode::F6 => return Some(Hotkey::Volume(-1)),
+        Keycode::F7 => return Some(Hotkey::Volume(1)),
+        Keycode::F1 => 1,
+        Keycode::F2 => 2,
+        Keycode::F3 => 3,
+        Keycode::F4 => 4,
+        _ => return None,
+    };
+    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+        Some(Hotkey::SaveState(slot))
+    } else {
+        Some(Hotkey::LoadState(slot))
+    }
+}
@@ -0,0 +1,43 @@
+//! Emulator core for the CHIP-8 virtual machine.
+//!
+//! The core has no platform dependencies: frontends feed it keypad state,
+//! step it, and read back the framebuffer and timers.
+
+#[cfg(test)]
+extern crate tempfile;
+
+extern crate rand;
+
+pub mod assembler;
+pub mod audio;
+pub mod cheat;
+pub mod cpu;
+pub mod debugger;
+pub mod error;
+pub mod font;
+pub mod image;
+pub mod instruction;
+pub mod movie;
+pub mod profile;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+mod state;
+pub mod trace;
+pub mod watch;
+
+pub use crate::assembler::{assemble, assemble_at};
+pub use crate::audio::{AudioStream, Buzzer, Tone, WavWriter, Waveform};
+pub use crate::cheat::{Cheat, CheatSearch, Comparison};
+pub use crate::cpu::{Framebuffer, StepOutcome, CPU};
+pub use crate::debugger::Debugger;
+pub use crate::error::{AssembleError, CheatError, CpuError, LoadError, MovieError, StateError, WavError};
+pub use crate::font::Font;
+pub use crate::instruction::{decode, disassemble, Instruction};
+pub use crate::movie::{Frame, Movie};
+pub use crate::profile::Profiler;
+pub use crate::quirks::Quirks;
+pub use crate::rewind::Rewind;
+pub use crate::rng::{RandomSource, SplitMix64};
+pub use crate::trace::{TraceFilter, TraceFormat, Tracer};
+pub use crate::watch::{Access, WatchHit, Watchpoint};
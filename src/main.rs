@@ -1,48 +1,754 @@
-#[cfg(test)]
-extern crate tempfile;
+#[cfg(feature = "sdl")]
+extern crate sdl2;
 
+extern crate chip8;
 extern crate rand;
-extern crate sdl2;
 
-mod cpu;
+#[cfg(feature = "sdl")]
 mod display;
+#[cfg(feature = "sdl")]
 mod keyboard;
+mod options;
+#[cfg(feature = "sdl")]
 mod sound;
+mod terminal;
 
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use chip8::cheat::read_cheats;
+#[cfg(feature = "sdl")]
+use chip8::cheat::write_cheats;
+use chip8::{assemble_at, disassemble, image, AudioStream, Cheat, CheatError, CpuError, Frame, Movie, MovieError, Profiler, StepOutcome, TraceFormat, Tracer, WavError, WavWriter, CPU};
+#[cfg(feature = "sdl")]
+use chip8::debugger::Resume;
+use chip8::Rewind;
+use chip8::audio::read_wav;
+#[cfg(feature = "sdl")]
+use chip8::{Debugger, StateError};
+use chip8::cpu::{CHIP8_N_KEYS, CHIP8_TIMER_HZ, MAX_IPS};
 
+#[cfg(feature = "sdl")]
 use crate::display::Display;
-use crate::keyboard::Keyboard;
+#[cfg(feature = "sdl")]
+use crate::keyboard::{Hotkey, Keyboard};
+use crate::options::{Command, KeyPress, Options};
+#[cfg(feature = "sdl")]
 use crate::sound::Sound;
+use crate::terminal::Terminal;
+
+// Speed multiplier applied while the turbo key is held, up to `MAX_IPS`.
+const TURBO_FACTOR: u32 = 4;
+
+// How far behind real time the loop may fall before it stops trying to catch up.
+const MAX_FRAME_LAG: u32 = 5;
+
+// Audio recorded without a sound device to match.
+const AUDIO_SAMPLE_RATE: u32 = 44100;
+
+// Steps of the volume hotkeys.
+#[cfg(feature = "sdl")]
+const VOLUME_STEP: f32 = 0.05;
+
+// The movie being recorded or played back, if any.
+enum MovieMode {
+    Off,
+    Recording(Movie),
+    // With the index of the next frame to play.
+    Playing(Movie, usize),
+}
+
+impl MovieMode {
+
+    // The input for the next frame: `live` is recorded, or replaced by the
+    // movie's. Live input takes over at the end of the movie.
+    fn next_frame(&mut self, live: Frame) -> Frame {
+        if let MovieMode::Playing(ref recorded, index) = *self {
+            if index == recorded.frames.len() {
+                eprintln!("chip8: end of movie, live input takes over");
+                *self = MovieMode::Off;
+            }
+        }
+        match *self {
+            MovieMode::Recording(ref mut movie) => {
+                movie.frames.push(live);
+                live
+            }
+            MovieMode::Playing(ref recorded, ref mut index) => {
+                *index += 1;
+                recorded.frames[*index - 1]
+            }
+            MovieMode::Off => live,
+        }
+    }
+
+    // Forgets the last frame after the machine was rewound by one.
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    fn step_back(&mut self) {
+        match *self {
+            MovieMode::Recording(ref mut movie) => {
+                movie.frames.pop();
+            }
+            MovieMode::Playing(_, ref mut index) => *index = index.saturating_sub(1),
+            MovieMode::Off => {}
+        }
+    }
+}
 
 fn main() {
 
-    let mut cpu = cpu::CPU::new();
-    let args: Vec<String> = env::args().collect();
-    let filename = &args[1];
-    let mut file = File::open(filename).unwrap();
-    cpu.load_game(&mut file);
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("chip8: {}\n\n{}", e, Options::usage());
+            process::exit(2);
+        }
+    };
+
+    if options.command == Command::Disasm {
+        disasm(&options);
+        return;
+    }
+
+    let rom = read_rom(&options);
+
+    if let Some(ref path) = options.play {
+        let movie = File::open(path)
+            .map_err(MovieError::from)
+            .and_then(|file| Movie::read(BufReader::new(file)));
+        let movie = match movie {
+            Ok(movie) => movie,
+            Err(e) => {
+                eprintln!("chip8: cannot play '{}': {}", path, e);
+                process::exit(1);
+            }
+        };
+        match movie.machine(&rom) {
+            Ok(cpu) => start(cpu, &options, MovieMode::Playing(movie, 0)),
+            Err(e) => {
+                eprintln!("chip8: cannot play '{}': {}", path, e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Always seeded explicitly, so that a recording can reproduce the run.
+    let seed = options.seed.unwrap_or_else(rand::random);
+    let mut cpu = CPU::new(options.quirks);
+    cpu.seed(seed);
+    let loaded = cpu.load_font(options.font, options.font_address).and_then(|_| cpu.load_rom_at(&rom, options.load_address));
+    if let Err(e) = loaded {
+        eprintln!("chip8: cannot load '{}': {}", options.rom, e);
+        process::exit(1);
+    }
+
+    let movie = match options.record {
+        Some(_) => MovieMode::Recording(Movie::new(&cpu, seed, options.load_address)),
+        None => MovieMode::Off,
+    };
+    start(cpu, &options, movie);
+}
+
+fn start(mut cpu: CPU, options: &Options, movie: MovieMode) {
+    // Movies replay the ROM as recorded, without cheats.
+    if let MovieMode::Off = movie {
+        load_cheats(&mut cpu, &options.rom);
+    }
+    if options.headless {
+        run_headless(cpu, options, movie);
+    } else if options.tui {
+        run_tui(cpu, options, movie);
+    } else {
+        run(cpu, options, movie);
+    }
+}
+
+// Reads the ROM image, assembling it first if it is Octo source (`.8o`).
+fn read_rom(options: &Options) -> Vec<u8> {
+    let rom = match fs::read(&options.rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("chip8: cannot open '{}': {}", options.rom, e);
+            process::exit(1);
+        }
+    };
+    if !options.rom.ends_with(".8o") {
+        return rom;
+    }
+    let source = String::from_utf8_lossy(&rom);
+    match assemble_at(&source, options.load_address) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("chip8: {}:{}", options.rom, e);
+            process::exit(1);
+        }
+    }
+}
+
+// Prints a listing of the whole ROM as if it were loaded at the load address.
+fn disasm(options: &Options) {
+    let rom = read_rom(options);
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for line in disassemble(&rom, options.load_address) {
+        // Stop quietly when the reader goes away, e.g. when piped into `head`.
+        if writeln!(out, "{}", line).is_err() {
+            return;
+        }
+    }
+    let _ = out.flush();
+}
+
+#[cfg(feature = "sdl")]
+fn run(mut cpu: CPU, options: &Options, mut movie: MovieMode) {
 
     let sdl_context = sdl2::init().unwrap();
 
     let mut display = Display::new(&sdl_context);
     let mut keyboard = Keyboard::new(&sdl_context);
-    let sound = Sound::new(&sdl_context);
+    let mut sound = Sound::new(&sdl_context);
+    enable_audio(&mut cpu, options, sound.sample_rate());
+
+    let frame_period = Duration::from_secs(1) / CHIP8_TIMER_HZ;
+    let mut next_frame = Instant::now() + frame_period;
+    // Carries the fractional part of ips / 60 over to the next frame.
+    let mut cycle_budget: u32 = 0;
+    // Set once the CPU faults; the window stays open on the last frame.
+    let mut halted = false;
+
+    let mut rewind = Rewind::new(options.rewind_memory);
+    rewind.push(&cpu);
+    let mut instruments = Instruments::open(&mut cpu, options);
+
+    let mut debugger = if options.debug { Some(Debugger::new()) } else { None };
+    if let Some(ref mut debugger) = debugger {
+        if debugger_repl(debugger, &mut cpu) == Resume::Quit {
+            save_cheats(&cpu, &options.rom);
+            return;
+        }
+    }
 
     while let Ok(keypad) = keyboard.poll() {
-        cpu.tick(keypad);
-        if cpu.sound_timer > 0 {
-            sound.start_beep();
-        } else {
-            sound.stop_beep();
+        for hotkey in keyboard.take_hotkeys() {
+            match hotkey {
+                Hotkey::SaveState(slot) => save_state(&cpu, &options.rom, slot),
+                Hotkey::LoadState(_) if !matches!(movie, MovieMode::Off) => {
+                    eprintln!("chip8: states cannot be loaded while a movie is recorded or played");
+                }
+                Hotkey::LoadState(slot) => {
+                    if load_state(&mut cpu, &options.rom, slot) {
+                        halted = false;
+                    }
+                }
+                Hotkey::Mute => {
+                    if let Some(audio) = cpu.audio_mut() {
+                        let muted = !audio.muted();
+                        audio.set_muted(muted);
+                        eprintln!("chip8: sound {}", if muted { "muted" } else { "on" });
+                    }
+                }
+                Hotkey::Volume(steps) => {
+                    if let Some(audio) = cpu.audio_mut() {
+                        let volume = audio.volume() + steps as f32 * VOLUME_STEP;
+                        audio.set_volume(volume);
+                        eprintln!("chip8: volume {:.0}%", audio.volume() * 100.0);
+                    }
+                }
+            }
+        }
+
+        let ips = if keyboard.turbo() { options.ips.saturating_mul(TURBO_FACTOR).min(MAX_IPS) } else { options.ips };
+        cycle_budget = cycle_budget.saturating_add(ips);
+        let instructions = cycle_budget / CHIP8_TIMER_HZ;
+        cycle_budget %= CHIP8_TIMER_HZ;
+
+        let mut redraw = false;
+        let rewinding = keyboard.rewinding();
+        if rewinding {
+            match rewind.step_back(&mut cpu) {
+                Ok(stepped) => {
+                    redraw = stepped;
+                    halted &= !stepped;
+                    if stepped {
+                        movie.step_back();
+                    }
+                }
+                Err(e) => eprintln!("chip8: cannot rewind: {}", e),
+            }
+        } else if !halted {
+            let frame = movie.next_frame(Frame { keypad, instructions });
+            let instructions = frame.instructions;
+            cpu.set_keypad(frame.keypad);
+            match debugger {
+                Some(ref mut debugger) => match debugger.run_frame(&mut cpu, instructions) {
+                    Ok(frame_redraw) => redraw = frame_redraw,
+                    Err(stop) => {
+                        eprintln!("chip8: {}", stop);
+                        display.draw(cpu.vram(), cpu.resolution());
+                        if debugger_repl(debugger, &mut cpu) == Resume::Quit {
+                            break;
+                        }
+                        // Don't try to catch up on the time spent in the debugger.
+                        next_frame = Instant::now();
+                    }
+                },
+                None => match instruments.run_frame(&mut cpu, instructions) {
+                    Ok(frame_redraw) => redraw = frame_redraw,
+                    Err(e) => {
+                        eprintln!("chip8: {}; execution halted.", e);
+                        halted = true;
+                    }
+                },
+            }
+            rewind.push(&cpu);
+        }
+
+        let samples = cpu.take_audio();
+        instruments.record_audio(&samples);
+        sound.play(&samples);
+        if redraw {
+            display.draw(cpu.vram(), cpu.resolution());
+        }
+
+        if cpu.exited() {
+            break;
+        }
+
+        let now = Instant::now();
+        if now < next_frame {
+            thread::sleep(next_frame - now);
+        } else if now - next_frame > frame_period * MAX_FRAME_LAG {
+            next_frame = now;
+        }
+        next_frame += frame_period;
+    }
+
+    if let (MovieMode::Recording(ref movie), Some(ref path)) = (movie, &options.record) {
+        save_movie(movie, path);
+    }
+    instruments.finish(&cpu, options);
+    save_cheats(&cpu, &options.rom);
+}
+
+// Runs in the terminal, like `run` but with no debugger or save states.
+fn run_tui(mut cpu: CPU, options: &Options, mut movie: MovieMode) {
+    let mut terminal = match Terminal::new() {
+        Ok(terminal) => terminal,
+        Err(e) => {
+            eprintln!("chip8: cannot use the terminal: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let frame_period = Duration::from_secs(1) / CHIP8_TIMER_HZ;
+    let mut next_frame = Instant::now() + frame_period;
+    let mut cycle_budget: u32 = 0;
+    let mut halted = false;
+    // The bell rings once when the sound timer starts.
+    let mut beeping = false;
+
+    let mut rewind = Rewind::new(options.rewind_memory);
+    rewind.push(&cpu);
+    let mut instruments = Instruments::open(&mut cpu, options);
+    terminal.draw(cpu.vram(), cpu.resolution());
+
+    while let Ok(keypad) = terminal.poll() {
+        let ips = if terminal.turbo() { options.ips.saturating_mul(TURBO_FACTOR).min(MAX_IPS) } else { options.ips };
+        cycle_budget = cycle_budget.saturating_add(ips);
+        let instructions = cycle_budget / CHIP8_TIMER_HZ;
+        cycle_budget %= CHIP8_TIMER_HZ;
+
+        let mut redraw = false;
+        let rewinding = terminal.rewinding();
+        if rewinding {
+            match rewind.step_back(&mut cpu) {
+                Ok(stepped) => {
+                    redraw = stepped;
+                    halted &= !stepped;
+                    if stepped {
+                        movie.step_back();
+                    }
+                }
+                Err(e) => eprintln!("chip8: cannot rewind: {}", e),
+            }
+        } else if !halted {
+            let frame = movie.next_frame(Frame { keypad, instructions });
+            cpu.set_keypad(frame.keypad);
+            match instruments.run_frame(&mut cpu, frame.instructions) {
+                Ok(frame_redraw) => redraw = frame_redraw,
+                Err(e) => {
+                    eprintln!("chip8: {}; execution halted.", e);
+                    halted = true;
+                }
+            }
+            rewind.push(&cpu);
+        }
+        instruments.record_audio(&cpu.take_audio());
+
+        let sounding = cpu.sound_timer() > 0 && !halted && !rewinding;
+        if sounding && !beeping && !options.mute {
+            terminal.beep();
+        }
+        beeping = sounding;
+        if redraw {
+            terminal.draw(cpu.vram(), cpu.resolution());
+        }
+
+        if cpu.exited() {
+            break;
+        }
+
+        let now = Instant::now();
+        if now < next_frame {
+            thread::sleep(next_frame - now);
+        } else if now - next_frame > frame_period * MAX_FRAME_LAG {
+            next_frame = now;
         }
-        if cpu.redraw {
-            display.draw(&cpu.vram);
+        next_frame += frame_period;
+    }
+
+    // Back to the normal screen before reporting anything.
+    drop(terminal);
+    if let (MovieMode::Recording(ref movie), Some(ref path)) = (movie, &options.record) {
+        save_movie(movie, path);
+    }
+    instruments.finish(&cpu, options);
+}
+
+// Runs `--frames` frames (by default the whole movie being played) as fast as
+// possible, with the keypad driven by `--key` scripts or the movie, then
+// writes out the last frame.
+fn run_headless(mut cpu: CPU, options: &Options, mut movie: MovieMode) {
+    let frames = match (options.frames, &movie) {
+        (Some(frames), _) => frames,
+        (None, MovieMode::Playing(recorded, _)) => recorded.frames.len() as u32,
+        (None, _) => 0,
+    };
+    let mut cycle_budget: u32 = 0;
+    let mut fault = None;
+    let mut instruments = Instruments::open(&mut cpu, options);
+
+    for frame in 0..frames {
+        cycle_budget = cycle_budget.saturating_add(options.ips);
+        let instructions = cycle_budget / CHIP8_TIMER_HZ;
+        cycle_budget %= CHIP8_TIMER_HZ;
+        let keypad = scripted_keypad(&options.keys, frame);
+
+        let frame = movie.next_frame(Frame { keypad, instructions });
+        cpu.set_keypad(frame.keypad);
+        let result = instruments.run_frame(&mut cpu, frame.instructions);
+        instruments.record_audio(&cpu.take_audio());
+        if let Err(e) = result {
+            fault = Some(e);
+            break;
+        }
+        if cpu.exited() {
+            break;
+        }
+    }
+
+    if let (MovieMode::Recording(ref movie), Some(ref path)) = (movie, &options.record) {
+        save_movie(movie, path);
+    }
+    instruments.finish(&cpu, options);
+    if let Err(e) = write_frame(&cpu, options) {
+        eprintln!("chip8: cannot write the frame: {}", e);
+        process::exit(1);
+    }
+    if let Some(e) = fault {
+        eprintln!("chip8: {}; execution halted.", e);
+        process::exit(1);
+    }
+}
+
+// The keys held down by the `--key` scripts during `frame`.
+fn scripted_keypad(keys: &[KeyPress], frame: u32) -> [bool; CHIP8_N_KEYS] {
+    let mut keypad = [false; CHIP8_N_KEYS];
+    for press in keys {
+        if frame >= press.frame && frame - press.frame < press.frames {
+            keypad[press.key] = true;
+        }
+    }
+    keypad
+}
+
+// Writes the visible framebuffer to `--output`, in the format its extension
+// names, and prints its hash with `--hash`. Without either, the frame goes to
+// stdout as ASCII art.
+fn write_frame(cpu: &CPU, options: &Options) -> io::Result<()> {
+    let vram = cpu.vram();
+    let resolution = cpu.resolution();
+    let stdout = io::stdout();
+    match options.output.as_deref() {
+        None if options.hash => {}
+        None | Some("-") => image::write_ascii(vram, resolution, stdout.lock())?,
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            if path.ends_with(".png") {
+                image::write_png(vram, resolution, &mut writer)?;
+            } else if path.ends_with(".pbm") {
+                image::write_pbm(vram, resolution, &mut writer)?;
+            } else {
+                image::write_ascii(vram, resolution, &mut writer)?;
+            }
+            writer.flush()?;
+        }
+    }
+    if options.hash {
+        println!("{:016x}", image::frame_hash(vram, resolution));
+    }
+    Ok(())
+}
+
+type TraceFile = Tracer<BufWriter<File>>;
+
+// The tools that watch the run: `--trace` and `--profile`, which see every
+// instruction, and `--record-audio`.
+struct Instruments {
+    tracer: Option<TraceFile>,
+    profiler: Option<Profiler>,
+    audio: Option<WavWriter<BufWriter<File>>>,
+}
+
+impl Instruments {
+
+    // Enables audio on `cpu` to record it, unless the frontend already did.
+    fn open(cpu: &mut CPU, options: &Options) -> Instruments {
+        let tracer = options.trace.as_ref().map(|path| {
+            let mut writer = match File::create(path) {
+                Ok(file) => BufWriter::new(file),
+                Err(e) => {
+                    eprintln!("chip8: cannot create '{}': {}", path, e);
+                    process::exit(1);
+                }
+            };
+            if options.trace_format == TraceFormat::Csv {
+                let _ = writeln!(writer, "cycle,pc,opcode,mnemonic,registers,i");
+            }
+            Tracer::new(writer, options.trace_format, options.trace_filter.clone())
+        });
+        let profiler = options.profile.as_ref().map(|_| Profiler::new());
+        let audio = options.record_audio.as_ref().map(|path| {
+            if cpu.audio().is_none() {
+                enable_audio(cpu, options, AUDIO_SAMPLE_RATE);
+            }
+            let sample_rate = cpu.audio().map_or(AUDIO_SAMPLE_RATE, AudioStream::sample_rate);
+            match File::create(path).and_then(|file| WavWriter::new(BufWriter::new(file), sample_rate)) {
+                Ok(writer) => writer,
+                Err(e) => {
+                    eprintln!("chip8: cannot create '{}': {}", path, e);
+                    process::exit(1);
+                }
+            }
+        });
+        Instruments { tracer, profiler, audio }
+    }
+
+    // Appends a frame's samples to the recording. Recording stops at the
+    // first error.
+    fn record_audio(&mut self, samples: &[f32]) {
+        if let Some(ref mut writer) = self.audio {
+            if let Err(e) = writer.write(samples) {
+                eprintln!("chip8: cannot record audio: {}", e);
+                self.audio = None;
+            }
+        }
+    }
+
+    // Runs a frame, one instruction at a time when tracing or profiling.
+    fn run_frame(&mut self, cpu: &mut CPU, instructions: u32) -> Result<bool, CpuError> {
+        if self.tracer.is_none() && self.profiler.is_none() {
+            return cpu.run_frame(instructions);
+        }
+        let mut redraw = false;
+        for _ in 0..instructions {
+            if let Some(ref mut profiler) = self.profiler {
+                profiler.count(cpu);
+            }
+            let outcome = match self.tracer {
+                Some(ref mut tracer) => tracer.step(cpu)?,
+                None => cpu.step()?,
+            };
+            redraw |= cpu.redraw();
+            if outcome == StepOutcome::Exited {
+                break;
+            }
+        }
+        cpu.end_frame();
+        Ok(redraw)
+    }
+
+    // Completes the trace and the audio recording, and writes the profile,
+    // disassembled from memory as it is at exit.
+    fn finish(self, cpu: &CPU, options: &Options) {
+        if let (Some(tracer), Some(path)) = (self.tracer, &options.trace) {
+            if let Err(e) = tracer.finish() {
+                eprintln!("chip8: cannot write '{}': {}", path, e);
+            }
         }
-        thread::sleep(Duration::from_millis(2));
+        if let (Some(writer), Some(path)) = (self.audio, &options.record_audio) {
+            if let Err(e) = writer.finish() {
+                eprintln!("chip8: cannot write '{}': {}", path, e);
+            }
+        }
+        if let (Some(profiler), Some(path)) = (self.profiler, &options.profile) {
+            write_profile(&profiler, cpu, path);
+        }
+    }
+}
+
+// Writes the report to `path`, and the folded stacks for flame graphs to
+// `path.folded`.
+fn write_profile(profiler: &Profiler, cpu: &CPU, path: &str) {
+    let folded = format!("{}.folded", path);
+    let report = File::create(path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        profiler.write_report(cpu, &mut writer)?;
+        writer.flush()
+    });
+    let stacks = File::create(&folded).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        profiler.write_folded(&mut writer)?;
+        writer.flush()
+    });
+    match report.and(stacks) {
+        Ok(()) => eprintln!("chip8: wrote the profile to '{}' and '{}'", path, folded),
+        Err(e) => eprintln!("chip8: cannot write the profile '{}': {}", path, e),
+    }
+}
+
+fn save_movie(movie: &Movie, path: &str) {
+    let result = File::create(path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        movie.write(&mut writer)?;
+        writer.flush()
+    });
+    match result {
+        Ok(()) => eprintln!("chip8: recorded {} frames to '{}'", movie.frames.len(), path),
+        Err(e) => eprintln!("chip8: cannot save '{}': {}", path, e),
+    }
+}
+
+// Runs the debugger on the terminal. The window is not updated meanwhile.
+#[cfg(feature = "sdl")]
+fn debugger_repl(debugger: &mut Debugger, cpu: &mut CPU) -> Resume {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    debugger.repl(cpu, stdin.lock(), stdout.lock()).unwrap_or(Resume::Quit)
+}
+
+// Save states live next to the ROM, one file per slot.
+#[cfg(feature = "sdl")]
+fn state_path(rom: &str, slot: u8) -> String {
+    format!("{}.state{}", rom, slot)
+}
+
+// Generates the buzzer's samples at `sample_rate`, with the tone from the
+// options and the `--beep-waveform` sample loaded if one was given.
+fn enable_audio(cpu: &mut CPU, options: &Options, sample_rate: u32) {
+    let mut tone = options.tone.clone();
+    if let Some(ref path) = options.beep_sample {
+        let sample = File::open(path).map_err(WavError::from).and_then(|file| read_wav(BufReader::new(file)));
+        match sample {
+            Ok(waveform) => tone.waveform = waveform,
+            Err(e) => {
+                eprintln!("chip8: cannot load '{}': {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    cpu.enable_audio(tone, sample_rate);
+    if let Some(audio) = cpu.audio_mut() {
+        audio.set_muted(options.mute);
+    }
+}
+
+// Cheats are kept per ROM, in a `cheats` directory next to it, by ROM hash
+// so that renaming the ROM keeps them.
+fn cheat_path(rom: &str, rom_hash: u64) -> PathBuf {
+    let directory = Path::new(rom).parent().unwrap_or_else(|| Path::new(""));
+    directory.join("cheats").join(format!("{:016x}.cht", rom_hash))
+}
+
+fn read_cheat_file(path: &Path) -> Result<Vec<Cheat>, CheatError> {
+    read_cheats(BufReader::new(File::open(path)?))
+}
+
+fn load_cheats(cpu: &mut CPU, rom: &str) {
+    let path = cheat_path(rom, cpu.rom_hash());
+    match read_cheat_file(&path) {
+        Ok(cheats) => {
+            if !cheats.is_empty() {
+                eprintln!("chip8: loaded {} cheats from '{}'", cheats.len(), path.display());
+            }
+            for cheat in cheats {
+                cpu.add_cheat(cheat);
+            }
+        }
+        Err(CheatError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => eprintln!("chip8: cannot load '{}': {}", path.display(), e),
+    }
+}
+
+// Writes the cheats back to the ROM's cheat file if they changed.
+#[cfg(feature = "sdl")]
+fn save_cheats(cpu: &CPU, rom: &str) {
+    let path = cheat_path(rom, cpu.rom_hash());
+    let saved = read_cheat_file(&path).unwrap_or_default();
+    if saved == cpu.cheats() {
+        return;
+    }
+    let result = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        write_cheats(cpu.cheats(), &mut writer)?;
+        writer.flush()
+    });
+    match result {
+        Ok(()) => eprintln!("chip8: saved {} cheats to '{}'", cpu.cheats().len(), path.display()),
+        Err(e) => eprintln!("chip8: cannot save '{}': {}", path.display(), e),
     }
+}
+
+#[cfg(feature = "sdl")]
+fn save_state(cpu: &CPU, rom: &str, slot: u8) {
+    let path = state_path(rom, slot);
+    let result = File::create(&path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        cpu.save_state(&mut writer)?;
+        writer.flush()
+    });
+    match result {
+        Ok(()) => eprintln!("chip8: saved state to slot {}", slot),
+        Err(e) => eprintln!("chip8: cannot save '{}': {}", path, e),
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn load_state(cpu: &mut CPU, rom: &str, slot: u8) -> bool {
+    let path = state_path(rom, slot);
+    let result = File::open(&path)
+        .map_err(StateError::from)
+        .and_then(|file| cpu.load_state(BufReader::new(file)));
+    match result {
+        Ok(()) => {
+            eprintln!("chip8: loaded state from slot {}", slot);
+            true
+        }
+        Err(e) => {
+            eprintln!("chip8: cannot load '{}': {}", path, e);
+            false
+        }
+    }
+}
 
+#[cfg(not(feature = "sdl"))]
+fn run(_cpu: CPU, _options: &Options, _movie: MovieMode) {
+    eprintln!("chip8 was built without a window; rebuild with `--features sdl`, or use --tui or --headless.");
+    process::exit(1);
 }
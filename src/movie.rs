@@ -0,0 +1,186 @@
+use std::io::{self, Read, Write};
+
+use crate::cpu::{CHIP8_N_KEYS, CHIP8_PROGRAM_START, CHIP8_RAM_SIZE, CHIP8_TIMER_HZ, CPU, MAX_IPS, XOCHIP_RAM_SIZE};
+use crate::error::{CpuError, MovieError};
+use crate::font::{Font, BIG_FONT_SIZE, FONT_SIZE};
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::state::{fnv1a, StateReader, StateWriter};
+
+// Movies: the keypad state and instruction count of every frame of a run,
+// together with everything else the run depends on (ROM, RNG seed, quirks and
+// font). Playing a movie back on a fresh machine reproduces the run exactly.
+
+const MOVIE_MAGIC: [u8; 4] = *b"C8MV";
+// Bump whenever the layout written by `Movie::write` changes.
+const MOVIE_VERSION: u16 = 3;
+
+// The most instructions a frame can run at `MAX_IPS`.
+const MAX_FRAME_INSTRUCTIONS: u32 = MAX_IPS.div_ceil(CHIP8_TIMER_HZ);
+
+// Input for one 60 Hz frame, as passed to `CPU::set_keypad` and `CPU::run_frame`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub keypad: [bool; CHIP8_N_KEYS],
+    pub instructions: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub font: Font,
+    pub font_address: usize,
+    pub load_address: usize,
+    pub frames: Vec<Frame>,
+}
+
+impl Movie {
+
+    // Starts recording a machine that has just been seeded with `seed` and had
+    // its ROM loaded at `load_address`.
+    pub fn new(cpu: &CPU, seed: u64, load_address: usize) -> Movie {
+        Movie {
+            rom_hash: cpu.rom_hash(),
+            seed,
+            quirks: cpu.quirks(),
+            font: cpu.font(),
+            font_address: cpu.font_address(),
+            load_address,
+            frames: Vec::new(),
+        }
+    }
+
+    // A machine set up like the recorded one, with `rom` loaded.
+    pub fn machine(&self, rom: &[u8]) -> Result<CPU, MovieError> {
+        let found = fnv1a(rom);
+        if found != self.rom_hash {
+            return Err(MovieError::RomMismatch { expected: found, found: self.rom_hash });
+        }
+        let mut cpu = CPU::new(self.quirks);
+        cpu.load_font(self.font, self.font_address)?;
+        cpu.seed(self.seed);
+        cpu.load_rom_at(rom, self.load_address)?;
+        Ok(cpu)
+    }
+
+    // Plays frame number `index` on `cpu`. Returns whether the frame redrew.
+    pub fn play_frame(&self, cpu: &mut CPU, index: usize) -> Result<bool, CpuError> {
+        let frame = &self.frames[index];
+        cpu.set_keypad(frame.keypad);
+        cpu.run_frame(frame.instructions)
+    }
+
+    // Plays the whole movie on `cpu`.
+    pub fn play(&self, cpu: &mut CPU) -> Result<(), CpuError> {
+        for index in 0..self.frames.len() {
+            self.play_frame(cpu, index)?;
+        }
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut w = StateWriter::new(writer);
+        w.bytes(&MOVIE_MAGIC)?;
+        w.u16(MOVIE_VERSION)?;
+        w.u64(self.rom_hash)?;
+        w.u64(self.seed)?;
+        w.bool(self.quirks.logic_resets_vf)?;
+        w.bool(self.quirks.shift_uses_vy)?;
+        w.u8(match self.quirks.index_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => 1,
+            IndexIncrement::ByXPlusOne => 2,
+        })?;
+        w.bool(self.quirks.jump_uses_vx)?;
+        w.bool(self.quirks.clip_sprites)?;
+        w.u32(self.quirks.memory_size as u32)?;
+        w.bool(self.quirks.key_wait_release)?;
+        w.bool(self.quirks.index_sets_vf)?;
+        w.u8(match self.font {
+            Font::Chip8 => 0,
+            Font::Vip => 1,
+            Font::Dream6800 => 2,
+        })?;
+        w.u32(self.font_address as u32)?;
+        w.u32(self.load_address as u32)?;
+        w.u32(self.frames.len() as u32)?;
+        for frame in &self.frames {
+            let keypad = frame.keypad.iter().rev().fold(0, |bits, &pressed| bits << 1 | pressed as u16);
+            w.u16(keypad)?;
+            w.u32(frame.instructions)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: R) -> Result<Movie, MovieError> {
+        let mut r = StateReader::new(reader);
+        let mut magic = [0; 4];
+        r.bytes(&mut magic)?;
+        if magic != MOVIE_MAGIC {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = r.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_hash = r.u64()?;
+        let seed = r.u64()?;
+        let logic_resets_vf = r.bool()?;
+        let shift_uses_vy = r.bool()?;
+        let index_increment = match r.u8()? {
+            0 => IndexIncrement::Unchanged,
+            1 => IndexIncrement::ByX,
+            2 => IndexIncrement::ByXPlusOne,
+            _ => return Err(MovieError::Corrupt("index increment quirk")),
+        };
+        let jump_uses_vx = r.bool()?;
+        let clip_sprites = r.bool()?;
+        let memory_size = r.u32()? as usize;
+        if memory_size != CHIP8_RAM_SIZE && memory_size != XOCHIP_RAM_SIZE {
+            return Err(MovieError::Corrupt("memory size"));
+        }
+        let key_wait_release = r.bool()?;
+        let index_sets_vf = r.bool()?;
+        let quirks = Quirks {
+            logic_resets_vf,
+            shift_uses_vy,
+            index_increment,
+            jump_uses_vx,
+            clip_sprites,
+            memory_size,
+            key_wait_release,
+            index_sets_vf,
+        };
+        let font = match r.u8()? {
+            0 => Font::Chip8,
+            1 => Font::Vip,
+            2 => Font::Dream6800,
+            _ => return Err(MovieError::Corrupt("font")),
+        };
+        let font_address = r.u32()? as usize;
+        if font_address + FONT_SIZE + BIG_FONT_SIZE > CHIP8_PROGRAM_START {
+            return Err(MovieError::Corrupt("font address"));
+        }
+        let load_address = r.u32()? as usize;
+        let count = r.u32()? as usize;
+        let mut frames = Vec::new();
+        for _ in 0..count {
+            let bits = r.u16()?;
+            let mut keypad = [false; CHIP8_N_KEYS];
+            for (key, pressed) in keypad.iter_mut().enumerate() {
+                *pressed = bits & (1 << key) != 0;
+            }
+            let instructions = r.u32()?;
+            if instructions > MAX_FRAME_INSTRUCTIONS {
+                return Err(MovieError::Corrupt("instruction count"));
+            }
+            frames.push(Frame { keypad, instructions });
+        }
+        Ok(Movie { rom_hash, seed, quirks, font, font_address, load_address, frames })
+    }
+}
+
+#[cfg(test)]
+#[path = "./movie_tests.rs"]
+mod movie_tests;
@@ -0,0 +1,113 @@
+use crate::cpu::CPU;
+use crate::error::MovieError;
+use crate::font::Font;
+use crate::movie::*;
+use crate::quirks::Quirks;
+
+// Draws the digit of every key pressed at a random position.
+static PROGRAM: [u8; 12] = [
+    0xF1, 0x0A, // LD V1, K
+    0xC0, 0x3F, // RND V0, 0x3F
+    0xC2, 0x1F, // RND V2, 0x1F
+    0xF1, 0x29, // LD F, V1
+    0xD0, 0x25, // DRW V0, V2, 5
+    0x12, 0x00, // JP 0x200
+];
+
+fn snapshot(cpu: &CPU) -> Vec<u8> {
+    let mut state = Vec::new();
+    cpu.save_state(&mut state).unwrap();
+    state
+}
+
+// Records 300 frames of input and returns the movie and the final machine.
+fn record() -> (Movie, CPU) {
+    let mut cpu = CPU::new(Quirks::cosmac_vip());
+    cpu.load_font(Font::Vip, 0x80).unwrap();
+    cpu.seed(42);
+    cpu.load_rom(&PROGRAM).unwrap();
+    let mut movie = Movie::new(&cpu, 42, 0x200);
+    for index in 0..300 {
+        let mut keypad = [false; 16];
+        if index % 7 < 3 {
+            keypad[index % 16] = true;
+        }
+        let frame = Frame { keypad, instructions: 5 + index as u32 % 4 };
+        movie.frames.push(frame);
+        movie.play_frame(&mut cpu, index).unwrap();
+    }
+    (movie, cpu)
+}
+
+#[test]
+fn playback_reproduces_the_run() {
+    let (movie, recorded) = record();
+    let mut file = Vec::new();
+    movie.write(&mut file).unwrap();
+    let movie = Movie::read(&file[..]).unwrap();
+    assert_eq!(movie.frames.len(), 300);
+    assert_eq!(movie.quirks, Quirks::cosmac_vip());
+    assert_eq!(movie.font, Font::Vip);
+
+    let mut cpu = movie.machine(&PROGRAM).unwrap();
+    movie.play(&mut cpu).unwrap();
+    assert_eq!(snapshot(&cpu), snapshot(&recorded));
+    assert!(cpu.vram().iter().any(|row| row.iter().any(|&pixel| pixel != 0)));
+}
+
+#[test]
+fn playback_depends_on_the_seed() {
+    let (mut movie, recorded) = record();
+    movie.seed = 43;
+    let mut cpu = movie.machine(&PROGRAM).unwrap();
+    movie.play(&mut cpu).unwrap();
+    assert_ne!(snapshot(&cpu), snapshot(&recorded));
+}
+
+#[test]
+fn playback_needs_the_same_rom() {
+    let (movie, _) = record();
+    match movie.machine(&PROGRAM[..10]) {
+        Err(MovieError::RomMismatch { .. }) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn invalid_movie_is_rejected() {
+    match Movie::read(&b"C8ST\x01\x00"[..]) {
+        Err(MovieError::NotAMovie) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    let (movie, _) = record();
+    let mut file = Vec::new();
+    movie.write(&mut file).unwrap();
+    file[4] = 0xFF;
+    match Movie::read(&file[..]) {
+        Err(MovieError::UnsupportedVersion(_)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    file[4] = MOVIE_VERSION as u8;
+    // The memory size follows the header, the seed and five quirks.
+    let memory_size = file[27..31].to_vec();
+    file[27..31].copy_from_slice(&[0xFF; 4]);
+    match Movie::read(&file[..]) {
+        Err(MovieError::Corrupt("memory size")) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    file[27..31].copy_from_slice(&memory_size);
+    // The file ends with the last frame's instruction count.
+    let end = file.len();
+    let instructions = file[end - 4..].to_vec();
+    file[end - 4..].copy_from_slice(&[0xFF; 4]);
+    match Movie::read(&file[..]) {
+        Err(MovieError::Corrupt("instruction count")) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    file[end - 4..].copy_from_slice(&instructions);
+    file.truncate(file.len() - 1);
+    match Movie::read(&file[..]) {
+        Err(MovieError::Io(_)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
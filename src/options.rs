@@ -0,0 +1,445 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use chip8::cpu::{CHIP8_PROGRAM_START, MAX_IPS};
+use chip8::font::{BIG_FONT_SIZE, DEFAULT_FONT_ADDRESS, FONT_SIZE};
+use chip8::{Font, Quirks, Tone, TraceFilter, TraceFormat, Waveform};
+
+const DEFAULT_IPS: u32 = 700;
+
+// Memory set aside for rewinding, in MiB.
+const DEFAULT_REWIND_MEMORY: usize = 16;
+
+// The fonts have to live in the interpreter area, below the program.
+const FONT_AREA_END: usize = 0x200;
+
+// What to do with the ROM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    // Run it, in a window unless `--headless` is given.
+    Run,
+    // Print a disassembly listing.
+    Disasm,
+}
+
+// A scripted key press for headless runs: `key` is held down from frame
+// `frame` for `frames` frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyPress {
+    pub frame: u32,
+    pub key: usize,
+    pub frames: u32,
+}
+
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub struct Options {
+    pub command: Command,
+    pub rom: String,
+    pub ips: u32,
+    pub quirks: Quirks,
+    pub font: Font,
+    pub font_address: usize,
+    pub load_address: usize,
+    pub seed: Option<u64>,
+    pub debug: bool,
+    // In bytes.
+    pub rewind_memory: usize,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub headless: bool,
+    pub frames: Option<u32>,
+    pub keys: Vec<KeyPress>,
+    // Where the final frame is written; the format follows the extension.
+    pub output: Option<String>,
+    pub hash: bool,
+    pub tui: bool,
+    pub trace: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub profile: Option<String>,
+    pub tone: Tone,
+    // A WAV file to play instead of the tone's waveform.
+    pub beep_sample: Option<String>,
+    pub mute: bool,
+    pub record_audio: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum OptionsError {
+    MissingRom,
+    MissingValue(String),
+    InvalidValue(String, String),
+    UnknownOption(String),
+    Conflict(&'static str, &'static str),
+    Requires(&'static str, &'static str),
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionsError::MissingRom => write!(f, "no ROM file given"),
+            OptionsError::MissingValue(option) => write!(f, "{} requires a value", option),
+            OptionsError::InvalidValue(option, value) => {
+                write!(f, "invalid value '{}' for {}", value, option)
+            }
+            OptionsError::UnknownOption(option) => write!(f, "unknown option {}", option),
+            OptionsError::Conflict(first, second) => write!(f, "{} cannot be combined with {}", first, second),
+            OptionsError::Requires(first, second) => write!(f, "{} requires {}", first, second),
+        }
+    }
+}
+
+impl Options {
+
+    pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Options, OptionsError> {
+        let mut args = args.peekable();
+        let command = match args.peek().map(String::as_str) {
+            Some("disasm") => {
+                args.next();
+                Command::Disasm
+            }
+            Some("run") => {
+                args.next();
+                Command::Run
+            }
+            _ => Command::Run,
+        };
+        let mut rom = None;
+        let mut ips = DEFAULT_IPS;
+        let mut quirks = Quirks::default();
+        let mut font = Font::default();
+        let mut font_address = DEFAULT_FONT_ADDRESS;
+        let mut load_address = CHIP8_PROGRAM_START;
+        let mut seed = None;
+        let mut debug = false;
+        let mut rewind_memory = DEFAULT_REWIND_MEMORY << 20;
+        let mut record = None;
+        let mut play = None;
+        let mut headless = false;
+        let mut frames = None;
+        let mut keys = Vec::new();
+        let mut output = None;
+        let mut hash = false;
+        let mut tui = false;
+        let mut key_wait_release = None;
+        let mut trace = None;
+        let mut trace_format = TraceFormat::Text;
+        let mut trace_filter = TraceFilter::default();
+        let mut profile = None;
+        let mut tone = Tone::default();
+        let mut beep_sample = None;
+        let mut mute = false;
+        let mut record_audio = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--ips" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    ips = match value.parse() {
+                        Ok(ips) if ips > 0 && ips <= MAX_IPS => ips,
+                        _ => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--quirks" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    quirks = match Quirks::from_name(&value) {
+                        Some(quirks) => quirks,
+                        None => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--key-wait" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    key_wait_release = match value.as_str() {
+                        "press" => Some(false),
+                        "release" => Some(true),
+                        _ => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--font" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    font = match Font::from_name(&value) {
+                        Some(font) => font,
+                        None => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--font-address" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    font_address = match parse_address(&value) {
+                        Some(address) if address.checked_add(FONT_SIZE + BIG_FONT_SIZE).is_some_and(|end| end <= FONT_AREA_END) => {
+                            address
+                        }
+                        _ => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--load-address" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    load_address = match parse_address(&value) {
+                        Some(address) => address,
+                        None => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--seed" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    seed = match value.parse() {
+                        Ok(seed) => Some(seed),
+                        Err(_) => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--debug" => debug = true,
+                "--rewind-memory" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    rewind_memory = match value.parse::<usize>() {
+                        Ok(mib) if mib <= 4096 => mib << 20,
+                        _ => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--record" => {
+                    record = Some(args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?);
+                }
+                "--play" => {
+                    play = Some(args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?);
+                }
+                "--headless" => headless = true,
+                "--frames" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    frames = match value.parse() {
+                        Ok(frames) => Some(frames),
+                        Err(_) => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--key" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    match parse_key_press(&value) {
+                        Some(press) => keys.push(press),
+                        None => return Err(OptionsError::InvalidValue(arg, value)),
+                    }
+                }
+                "--output" => {
+                    output = Some(args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?);
+                }
+                "--hash" => hash = true,
+                "--tui" => tui = true,
+                "--trace" => {
+                    trace = Some(args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?);
+                }
+                "--profile" => {
+                    profile = Some(args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?);
+                }
+                "--trace-format" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    trace_format = match TraceFormat::from_name(&value) {
+                        Some(format) => format,
+                        None => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--trace-range" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    trace_filter.addresses = match parse_range(&value, parse_address) {
+                        Some(range) => Some(range),
+                        None => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--trace-ops" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    trace_filter.classes = match parse_classes(&value) {
+                        Some(classes) => classes,
+                        None => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--trace-cycles" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    trace_filter.cycles = match parse_range(&value, |n| n.parse().ok()) {
+                        Some(range) => Some(range),
+                        None => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--beep-frequency" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    tone.frequency = match value.parse() {
+                        Ok(frequency) if frequency > 0.0 && frequency <= 20000.0 => frequency,
+                        _ => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--beep-waveform" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    if let Some(waveform) = Waveform::from_name(&value) {
+                        tone.waveform = waveform;
+                        beep_sample = None;
+                    } else if value.ends_with(".wav") {
+                        beep_sample = Some(value);
+                    } else {
+                        return Err(OptionsError::InvalidValue(arg, value));
+                    }
+                }
+                "--volume" => {
+                    let value = args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?;
+                    tone.volume = match value.parse::<u32>() {
+                        Ok(percent) if percent <= 100 => percent as f32 / 100.0,
+                        _ => return Err(OptionsError::InvalidValue(arg, value)),
+                    };
+                }
+                "--mute" => mute = true,
+                "--record-audio" => {
+                    record_audio = Some(args.next().ok_or_else(|| OptionsError::MissingValue(arg.clone()))?);
+                }
+                _ if arg.starts_with("--") => return Err(OptionsError::UnknownOption(arg)),
+                _ => rom = Some(arg),
+            }
+        }
+
+        // Overrides the profile, whichever option comes first.
+        if let Some(release) = key_wait_release {
+            quirks.key_wait_release = release;
+        }
+
+        if record.is_some() && play.is_some() {
+            return Err(OptionsError::Conflict("--record", "--play"));
+        }
+        if headless && frames.is_none() && play.is_none() {
+            return Err(OptionsError::Requires("--headless", "--frames"));
+        }
+        if headless && debug {
+            return Err(OptionsError::Conflict("--headless", "--debug"));
+        }
+        if tui && headless {
+            return Err(OptionsError::Conflict("--tui", "--headless"));
+        }
+        // Both want the terminal's input.
+        if tui && debug {
+            return Err(OptionsError::Conflict("--tui", "--debug"));
+        }
+        if trace.is_some() && debug {
+            return Err(OptionsError::Conflict("--trace", "--debug"));
+        }
+        if profile.is_some() && debug {
+            return Err(OptionsError::Conflict("--profile", "--debug"));
+        }
+        // A break cuts the frame short, and commands change the machine, where
+        // the movie cannot follow.
+        if record.is_some() && debug {
+            return Err(OptionsError::Conflict("--record", "--debug"));
+        }
+
+        Ok(Options {
+            command,
+            rom: rom.ok_or(OptionsError::MissingRom)?,
+            ips,
+            quirks,
+            font,
+            font_address,
+            load_address,
+            seed,
+            debug,
+            rewind_memory,
+            record,
+            play,
+            headless,
+            frames,
+            keys,
+            output,
+            hash,
+            tui,
+            trace,
+            trace_format,
+            trace_filter,
+            profile,
+            tone,
+            beep_sample,
+            mute,
+            record_audio,
+        })
+    }
+
+    pub fn usage() -> &'static str {
+        "usage: chip8 [run] [OPTIONS] ROM\n\
+         \x20      chip8 disasm [--load-address N] ROM\n\
+         \n\
+         options:\n\
+         \x20 --ips N             instructions per second (default: 700)\n\
+         \x20 --quirks PROFILE    legacy, vip, chip48, schip or xochip\n\
+         \x20                     (default: legacy)\n\
+         \x20 --key-wait MODE     whether Fx0A returns on key press or release\n\
+         \x20                     (default: release)\n\
+         \x20 --font NAME         chip8, vip or dream6800 (default: chip8)\n\
+         \x20 --font-address N    font location, below 0x110 (default: 0x50)\n\
+         \x20 --load-address N    where the ROM is loaded and started (default: 0x200,\n\
+         \x20                     0x600 for ETI-660 programs)\n\
+         \x20 --seed N            seed the random number generator for a reproducible run\n\
+         \x20 --debug             start in the debugger, and enter it on faults\n\
+         \x20 --rewind-memory MIB memory for rewinding with Backspace (default: 16)\n\
+         \x20 --record FILE       record the input of the run to a movie file\n\
+         \x20 --play FILE         play back a movie; its settings override the options\n\
+         \x20 --tui               run in the terminal instead of a window\n\
+         \n\
+         sound:\n\
+         \x20 --beep-frequency HZ pitch of the buzzer (default: 240)\n\
+         \x20 --beep-waveform W   square, sine, triangle, noise or a .wav file to loop\n\
+         \x20                     (default: square)\n\
+         \x20 --volume PERCENT    buzzer volume (default: 25)\n\
+         \x20 --mute              start muted; F5 toggles, F6 and F7 change the volume\n\
+         \x20 --record-audio FILE write the sound of the run to a WAV file\n\
+         \n\
+         tracing and profiling:\n\
+         \x20 --trace FILE        write every instruction executed to FILE\n\
+         \x20 --trace-format FMT  text or csv (default: text)\n\
+         \x20 --trace-range A-B   only instructions at addresses A to B\n\
+         \x20 --trace-ops LIST    only opcode classes in LIST, e.g. 8,D,F\n\
+         \x20 --trace-cycles N-M  only instructions N to M, counted from 0\n\
+         \x20 --profile FILE      count the instructions executed and write a report\n\
+         \x20                     to FILE, and flame graph stacks to FILE.folded\n\
+         \n\
+         headless runs:\n\
+         \x20 --headless          run without a window, sound or keyboard\n\
+         \x20 --frames N          number of 60 Hz frames to run (default: the movie length)\n\
+         \x20 --key F:K[:N]       hold key K (hex) from frame F for N frames (default: 1)\n\
+         \x20 --output FILE       write the last frame as .png, .pbm or ASCII art (- for stdout)\n\
+         \x20 --hash              print a hash of the last frame"
+    }
+}
+
+// Parses FRAME:KEY[:FRAMES], with the key as a hex digit.
+fn parse_key_press(value: &str) -> Option<KeyPress> {
+    let mut parts = value.split(':');
+    let frame = parts.next()?.parse().ok()?;
+    let key = parts.next()?;
+    if key.len() != 1 {
+        return None;
+    }
+    let key = usize::from_str_radix(key, 16).ok()?;
+    let frames = match parts.next() {
+        Some(frames) => frames.parse().ok()?,
+        None => 1,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(KeyPress { frame, key, frames })
+}
+
+// Parses FIRST-LAST, both inclusive.
+fn parse_range<T: PartialOrd, F: Fn(&str) -> Option<T>>(value: &str, parse: F) -> Option<RangeInclusive<T>> {
+    let mut parts = value.splitn(2, '-');
+    let first = parse(parts.next()?)?;
+    let last = parse(parts.next()?)?;
+    if first > last {
+        return None;
+    }
+    Some(first..=last)
+}
+
+// Parses a comma separated list of hex digits.
+fn parse_classes(value: &str) -> Option<Vec<u8>> {
+    value
+        .split(',')
+        .map(|class| if class.len() == 1 { u8::from_str_radix(class, 16).ok() } else { None })
+        .collect()
+}
+
+// Parses a decimal or 0x-prefixed hexadecimal address.
+fn parse_address(value: &str) -> Option<usize> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        usize::from_str_radix(&value[2..], 16).ok()
+    } else {
+        value.parse().ok()
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::cpu::{StepOutcome, CPU};
+use crate::error::CpuError;
+use crate::instruction::disassemble;
+
+// An execution profiler: counts the instructions executed at every address,
+// in every opcode class (first hex digit) and in every call stack. Call
+// stacks are followed through the depth of the CPU stack, and name each
+// subroutine by its entry address.
+//
+// `Profiler::count` is called before each step; `Profiler::run_frame` does
+// that for a whole frame.
+
+// Addresses listed in the report.
+const HOT_ADDRESSES: usize = 20;
+
+#[derive(Debug, Default)]
+pub struct Profiler {
+    total: u64,
+    addresses: HashMap<usize, u64>,
+    classes: [u64; 16],
+    // Entry addresses of the subroutines being run, outermost first.
+    calls: Vec<usize>,
+    stacks: HashMap<Vec<usize>, u64>,
+}
+
+impl Profiler {
+
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    // Instructions counted so far.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn count_at(&self, address: usize) -> u64 {
+        self.addresses.get(&address).cloned().unwrap_or(0)
+    }
+
+    pub fn count_of_class(&self, class: usize) -> u64 {
+        self.classes[class & 0xF]
+    }
+
+    // Counts the instruction `cpu` is about to execute. Nothing is counted
+    // while it waits for a key or after it exited.
+    pub fn count(&mut self, cpu: &CPU) {
+        if cpu.waiting_for_key() || cpu.exited() {
+            return;
+        }
+        let pc = cpu.pc();
+        let opcode = match cpu.memory().get(pc) {
+            Some(&byte) => byte,
+            None => return,
+        };
+
+        // Returns pop subroutines, and a call has just jumped to the entry
+        // of a new one.
+        let depth = cpu.stack().len();
+        self.calls.truncate(depth);
+        while self.calls.len() < depth {
+            self.calls.push(pc);
+        }
+
+        self.total += 1;
+        *self.addresses.entry(pc).or_insert(0) += 1;
+        self.classes[(opcode >> 4) as usize] += 1;
+        match self.stacks.get_mut(&self.calls[..]) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.calls.clone(), 1);
+            }
+        }
+    }
+
+    // Counts and executes a single instruction, like `CPU::step`.
+    pub fn step(&mut self, cpu: &mut CPU) -> Result<StepOutcome, CpuError> {
+        self.count(cpu);
+        cpu.step()
+    }
+
+    // Runs one 60 Hz frame like `CPU::run_frame`, counting every instruction.
+    pub fn run_frame(&mut self, cpu: &mut CPU, instructions: u32) -> Result<bool, CpuError> {
+        let mut redraw = false;
+        for _ in 0..instructions {
+            let outcome = self.step(cpu)?;
+            redraw |= cpu.redraw();
+            if outcome == StepOutcome::Exited {
+                break;
+            }
+        }
+        cpu.end_frame();
+        Ok(redraw)
+    }
+
+    // Instructions executed in each subroutine: on its own (`self`) and
+    // together with the subroutines it called (`total`), by entry address.
+    pub fn subroutines(&self) -> Vec<(usize, u64, u64)> {
+        let mut subroutines: HashMap<usize, (u64, u64)> = HashMap::new();
+        for (stack, &count) in &self.stacks {
+            if let Some(&innermost) = stack.last() {
+                subroutines.entry(innermost).or_insert((0, 0)).0 += count;
+            }
+            let mut seen = Vec::new();
+            for &entry in stack {
+                // Recursion counts once.
+                if !seen.contains(&entry) {
+                    subroutines.entry(entry).or_insert((0, 0)).1 += count;
+                    seen.push(entry);
+                }
+            }
+        }
+        let mut subroutines: Vec<(usize, u64, u64)> =
+            subroutines.into_iter().map(|(entry, (own, total))| (entry, own, total)).collect();
+        subroutines.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        subroutines
+    }
+
+    // Writes a text report: the hottest addresses, disassembled from the
+    // memory of `cpu`, the opcode classes and the subroutines.
+    pub fn write_report<W: Write>(&self, cpu: &CPU, mut writer: W) -> io::Result<()> {
+        let share = |count: u64| 100.0 * count as f64 / self.total.max(1) as f64;
+        writeln!(writer, "{} instructions executed", self.total)?;
+
+        writeln!(writer, "\nhottest addresses:")?;
+        let mut addresses: Vec<(usize, u64)> = self.addresses.iter().map(|(&pc, &count)| (pc, count)).collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for &(pc, count) in addresses.iter().take(HOT_ADDRESSES) {
+            let memory = cpu.memory();
+            let code = &memory[pc..(pc + 4).min(memory.len())];
+            let line = &disassemble(code, pc)[0];
+            writeln!(writer, "{:>12} {:>6.2}%  {}", count, share(count), line)?;
+        }
+
+        writeln!(writer, "\nopcode classes:")?;
+        for (class, &count) in self.classes.iter().enumerate() {
+            if count > 0 {
+                writeln!(writer, "{:>12} {:>6.2}%  {:X}xxx", count, share(count), class)?;
+            }
+        }
+
+        writeln!(writer, "\nsubroutines (self, total):")?;
+        writeln!(writer, "{:>12} {:>6.2}%  {:>12} {:>6.2}%  main", self.own(&[]), share(self.own(&[])), self.total, 100.0)?;
+        for (entry, own, total) in self.subroutines() {
+            writeln!(writer, "{:>12} {:>6.2}%  {:>12} {:>6.2}%  {:#05X}", own, share(own), total, share(total), entry)?;
+        }
+        Ok(())
+    }
+
+    // Writes the call stacks in the folded format read by flame graph tools:
+    // one line per stack, with the frames separated by semicolons, followed
+    // by the number of instructions executed in it.
+    pub fn write_folded<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let mut frames = vec!["main".to_string()];
+                frames.extend(stack.iter().map(|entry| format!("sub_{:03X}", entry)));
+                format!("{} {}", frames.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    // Instructions executed with exactly `stack` as the call stack.
+    fn own(&self, stack: &[usize]) -> u64 {
+        self.stacks.get(stack).cloned().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+#[path = "./profile_tests.rs"]
+mod profile_tests;
@@ -0,0 +1,66 @@
+use crate::cpu::CPU;
+use crate::profile::*;
+use crate::quirks::Quirks;
+
+static PROGRAM: [u8; 18] = [
+    0x22, 0x08, // CALL 0x208
+    0x22, 0x08, // CALL 0x208
+    0x12, 0x04, // JP 0x204
+    0x00, 0x00,
+    0x22, 0x0E, // 0x208: CALL 0x20E
+    0x60, 0x01, // LD V0, 0x01
+    0x00, 0xEE, // RET
+    0x70, 0x01, // 0x20E: ADD V0, 0x01
+    0x00, 0xEE, // RET
+];
+
+fn profile() -> (Profiler, CPU) {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&PROGRAM).unwrap();
+    let mut profiler = Profiler::new();
+    profiler.run_frame(&mut cpu, 14).unwrap();
+    (profiler, cpu)
+}
+
+#[test]
+fn counts_addresses_and_classes() {
+    let (profiler, _) = profile();
+    assert_eq!(profiler.total(), 14);
+    assert_eq!(profiler.count_at(0x200), 1);
+    assert_eq!(profiler.count_at(0x204), 2);
+    assert_eq!(profiler.count_at(0x20E), 2);
+    assert_eq!(profiler.count_at(0x206), 0);
+    assert_eq!(profiler.count_of_class(0x2), 4);
+    assert_eq!(profiler.count_of_class(0x0), 4);
+    assert_eq!(profiler.count_of_class(0x7), 2);
+}
+
+#[test]
+fn follows_subroutines() {
+    let (profiler, _) = profile();
+    assert_eq!(profiler.subroutines(), vec![(0x208, 6, 10), (0x20E, 4, 4)]);
+    let mut folded = Vec::new();
+    profiler.write_folded(&mut folded).unwrap();
+    assert_eq!(String::from_utf8(folded).unwrap(), "main 4\nmain;sub_208 6\nmain;sub_208;sub_20E 4\n");
+}
+
+#[test]
+fn key_waits_are_not_counted() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&[0xF0, 0x0A]).unwrap();
+    let mut profiler = Profiler::new();
+    profiler.run_frame(&mut cpu, 100).unwrap();
+    assert_eq!(profiler.total(), 1);
+}
+
+#[test]
+fn report_lists_hottest_addresses() {
+    let (profiler, cpu) = profile();
+    let mut report = Vec::new();
+    profiler.write_report(&cpu, &mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with("14 instructions executed\n\nhottest addresses:\n"));
+    assert!(report.contains("           2  14.29%  0204  12 04        JP 0x204\n"));
+    assert!(report.contains("           4  28.57%  2xxx\n"));
+    assert!(report.contains("           6  42.86%            10  71.43%  0x208\n"));
+}
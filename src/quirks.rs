@@ -0,0 +1,131 @@
+use crate::cpu::{CHIP8_RAM_SIZE, XOCHIP_RAM_SIZE};
+
+// Behavioural differences between the CHIP-8 interpreters that ROMs were
+// written for. See https://chip-8.github.io/extensions/ and Timendus'
+// quirks test for the reference behaviour of each platform.
+
+// How Fx55 and Fx65 leave the I register after a register range transfer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexIncrement {
+    // I is left unchanged (SUPER-CHIP).
+    Unchanged,
+    // I is incremented by x (CHIP-48).
+    ByX,
+    // I is incremented by x + 1 (COSMAC VIP, XO-CHIP).
+    ByXPlusOne,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    // 8xy1, 8xy2 and 8xy3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    // 8xy6 and 8xyE shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    // Effect of Fx55 and Fx65 on I.
+    pub index_increment: IndexIncrement,
+    // Bnnn is decoded as Bxnn and jumps to xnn + Vx instead of nnn + V0.
+    pub jump_uses_vx: bool,
+    // Dxyn clips sprites at the screen edges instead of wrapping them around.
+    pub clip_sprites: bool,
+    // Addressable memory in bytes: 4 KiB, or 64 KiB on XO-CHIP.
+    pub memory_size: usize,
+    // Fx0A waits for a key to be pressed and released, as on the COSMAC VIP,
+    // instead of taking whichever key is down.
+    pub key_wait_release: bool,
+    // Fx1E sets VF to 1 when I ends up above 0xF00, and to 0 otherwise, as an
+    // overflow flag. No reference platform does this.
+    pub index_sets_vf: bool,
+}
+
+impl Quirks {
+
+    // This emulator's behaviour from before the quirks were configurable,
+    // which the classic CHIP-8 ROMs in circulation run fine with: shifts in
+    // place, Fx55 and Fx65 leave I alone, Bnnn adds V0 and sprites wrap.
+    pub fn legacy() -> Quirks {
+        Quirks {
+            logic_resets_vf: false,
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            memory_size: CHIP8_RAM_SIZE,
+            key_wait_release: true,
+            index_sets_vf: true,
+        }
+    }
+
+    // The original COSMAC VIP interpreter.
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            logic_resets_vf: true,
+            shift_uses_vy: true,
+            index_increment: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            memory_size: CHIP8_RAM_SIZE,
+            key_wait_release: true,
+            index_sets_vf: false,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            logic_resets_vf: false,
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::ByX,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            memory_size: CHIP8_RAM_SIZE,
+            key_wait_release: true,
+            index_sets_vf: false,
+        }
+    }
+
+    // SUPER-CHIP 1.1.
+    pub fn schip() -> Quirks {
+        Quirks {
+            logic_resets_vf: false,
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            memory_size: CHIP8_RAM_SIZE,
+            key_wait_release: true,
+            index_sets_vf: false,
+        }
+    }
+
+    // XO-CHIP, as implemented by Octo.
+    pub fn xochip() -> Quirks {
+        Quirks {
+            logic_resets_vf: false,
+            shift_uses_vy: true,
+            index_increment: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            memory_size: XOCHIP_RAM_SIZE,
+            key_wait_release: true,
+            index_sets_vf: false,
+        }
+    }
+
+    // Looks up a preset by its command line name.
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "legacy" => Some(Quirks::legacy()),
+            "vip" | "chip8" | "chip-8" => Some(Quirks::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Quirks::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::schip()),
+            "xochip" | "xo-chip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::legacy()
+    }
+}
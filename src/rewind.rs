@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+
+use crate::cpu::CPU;
+use crate::error::StateError;
+
+// Rewind history: one snapshot per frame, kept within a memory budget.
+//
+// Only the newest snapshot is stored in full. Every older frame is stored as
+// the XOR of itself and the frame after it, with runs of zero bytes (the parts
+// that did not change) run-length encoded. Applying a delta to a snapshot
+// therefore yields the previous frame, and the oldest deltas can be dropped
+// without touching the rest.
+pub struct Rewind {
+    budget: usize,
+    used: usize,
+    current: Option<Vec<u8>>,
+    // Oldest first.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+
+    // A history that keeps as many frames as fit in `budget` bytes.
+    pub fn new(budget: usize) -> Rewind {
+        Rewind { budget, used: 0, current: None, deltas: VecDeque::new() }
+    }
+
+    // Records the current state of the machine as the newest frame.
+    pub fn push(&mut self, cpu: &CPU) {
+        let mut snapshot = Vec::new();
+        cpu.save_state(&mut snapshot).expect("writing to memory cannot fail");
+        if let Some(previous) = self.current.take() {
+            self.used -= previous.len();
+            if previous.len() == snapshot.len() {
+                let delta = encode_delta(&snapshot, &previous);
+                self.used += delta.len();
+                self.deltas.push_back(delta);
+            } else {
+                // A machine with a different memory size starts a new history.
+                self.clear();
+            }
+        }
+        self.used += snapshot.len();
+        self.current = Some(snapshot);
+        while self.used > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Puts the machine back one frame. Returns false when the history is used up.
+    // The history is left as it was if the frame cannot be loaded.
+    pub fn step_back(&mut self, cpu: &mut CPU) -> Result<bool, StateError> {
+        let (current, delta) = match (self.current.as_mut(), self.deltas.back()) {
+            (Some(current), Some(delta)) => (current, delta),
+            _ => return Ok(false),
+        };
+        let mut previous = current.clone();
+        apply_delta(&mut previous, delta);
+        cpu.load_state(&previous[..])?;
+        *current = previous;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.used -= delta.len();
+        }
+        Ok(true)
+    }
+
+    // Number of frames the machine can be put back by.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    // Bytes taken by the history.
+    pub fn memory_used(&self) -> usize {
+        self.used
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+        self.used = 0;
+    }
+}
+
+// Encodes `a XOR b` as a sequence of (zero run, literal run, literal bytes),
+// with the run lengths as variable-length integers.
+fn encode_delta(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut pos = 0;
+    while pos < a.len() {
+        let zeros = a[pos..].iter().zip(&b[pos..]).take_while(|(x, y)| x == y).count();
+        pos += zeros;
+        let literals = a[pos..].iter().zip(&b[pos..]).take_while(|(x, y)| x != y).count();
+        write_varint(&mut delta, zeros);
+        write_varint(&mut delta, literals);
+        delta.extend(a[pos..pos + literals].iter().zip(&b[pos..]).map(|(x, y)| x ^ y));
+        pos += literals;
+    }
+    delta
+}
+
+fn apply_delta(snapshot: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut input = delta;
+    while !input.is_empty() {
+        let zeros = read_varint(&mut input);
+        let literals = read_varint(&mut input);
+        pos += zeros;
+        for (byte, x) in snapshot[pos..pos + literals].iter_mut().zip(&input[..literals]) {
+            *byte ^= x;
+        }
+        input = &input[literals..];
+        pos += literals;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = input[0];
+        *input = &input[1..];
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+#[path = "./rewind_tests.rs"]
+mod rewind_tests;
@@ -0,0 +1,105 @@
+use crate::cpu::CPU;
+use crate::error::StateError;
+use crate::quirks::Quirks;
+use crate::rewind::*;
+
+// Counts V0 up by one per instruction, forever.
+static COUNTER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+fn counter() -> CPU {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&COUNTER).unwrap();
+    cpu
+}
+
+#[test]
+fn step_back_restores_earlier_frames() {
+    let mut cpu = counter();
+    let mut rewind = Rewind::new(1 << 20);
+    rewind.push(&cpu);
+    for _ in 0..10 {
+        cpu.run_frame(2).unwrap();
+        rewind.push(&cpu);
+    }
+    assert_eq!(rewind.len(), 10);
+    assert_eq!(cpu.v()[0x0], 10);
+
+    for expected in (0..10).rev() {
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(cpu.v()[0x0], expected);
+    }
+    assert!(!rewind.step_back(&mut cpu).unwrap());
+    assert!(rewind.is_empty());
+}
+
+#[test]
+fn resumes_from_rewound_frame() {
+    let mut cpu = counter();
+    let mut rewind = Rewind::new(1 << 20);
+    rewind.push(&cpu);
+    for _ in 0..5 {
+        cpu.run_frame(2).unwrap();
+        rewind.push(&cpu);
+    }
+    rewind.step_back(&mut cpu).unwrap();
+    rewind.step_back(&mut cpu).unwrap();
+    assert_eq!(cpu.v()[0x0], 3);
+
+    // The frames that were rewound over are replaced by the new ones.
+    cpu.set_v(0x1, 0xAA);
+    cpu.run_frame(2).unwrap();
+    rewind.push(&cpu);
+    cpu.run_frame(2).unwrap();
+    rewind.push(&cpu);
+    assert_eq!(rewind.len(), 5);
+    rewind.step_back(&mut cpu).unwrap();
+    assert_eq!((cpu.v()[0x0], cpu.v()[0x1]), (4, 0xAA));
+    rewind.step_back(&mut cpu).unwrap();
+    assert_eq!((cpu.v()[0x0], cpu.v()[0x1]), (3, 0x00));
+}
+
+#[test]
+fn deltas_are_compact_and_bounded() {
+    let mut cpu = counter();
+    let mut snapshot = Vec::new();
+    cpu.save_state(&mut snapshot).unwrap();
+
+    let mut rewind = Rewind::new(snapshot.len() + 1000);
+    rewind.push(&cpu);
+    for _ in 0..500 {
+        cpu.run_frame(2).unwrap();
+        rewind.push(&cpu);
+    }
+    assert!(rewind.memory_used() <= snapshot.len() + 1000);
+    // A frame that only changes a couple of registers takes a few bytes.
+    assert!(rewind.len() > 1000 / 16, "{} frames", rewind.len());
+    assert!(rewind.len() < 500);
+
+    let v0 = cpu.v()[0x0];
+    let frames = rewind.len();
+    for _ in 0..frames {
+        assert!(rewind.step_back(&mut cpu).unwrap());
+    }
+    assert_eq!(cpu.v()[0x0], v0.wrapping_sub(frames as u8));
+}
+
+#[test]
+fn failed_step_back_keeps_the_history() {
+    let mut cpu = counter();
+    let mut rewind = Rewind::new(1 << 20);
+    rewind.push(&cpu);
+    for _ in 0..3 {
+        cpu.run_frame(2).unwrap();
+        rewind.push(&cpu);
+    }
+    let used = rewind.memory_used();
+
+    let mut other = CPU::new(Quirks::default());
+    other.load_rom(&[0x00, 0xE0]).unwrap();
+    assert!(matches!(rewind.step_back(&mut other), Err(StateError::RomMismatch { .. })));
+    assert_eq!(rewind.len(), 3);
+    assert_eq!(rewind.memory_used(), used);
+
+    assert!(rewind.step_back(&mut cpu).unwrap());
+    assert_eq!(cpu.v()[0x0], 2);
+}
@@ -0,0 +1,45 @@
+// Random number sources for Cxkk. The CPU owns one so that runs can be made
+// reproducible by seeding it, and so its state can go into save states.
+
+pub trait RandomSource {
+    fn next_u8(&mut self) -> u8;
+    // The generator state, as stored in save states.
+    fn state(&self) -> u64;
+    fn set_state(&mut self, state: u64);
+}
+
+// SplitMix64: tiny, fast, and well distributed for any seed, including 0.
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl RandomSource for SplitMix64 {
+
+    fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
@@ -1,11 +1,16 @@
-use sdl2;
-use sdl2::audio::{AudioDevice, AudioCallback, AudioSpecDesired};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 
+use chip8::cpu::CHIP8_TIMER_HZ;
+
+// Frames of audio the queue may hold before it is dropped to catch up.
+const MAX_QUEUED_FRAMES: u32 = 4;
+
+// Plays the samples generated by the CPU (see `CPU::enable_audio`), one
+// frame at a time.
 pub struct Sound {
-    device: AudioDevice<SquareWave>,
+    queue: AudioQueue<f32>,
 }
 
-
 impl Sound {
 
     pub fn new(sdl_context: &sdl2::Sdl) -> Self {
@@ -17,44 +22,27 @@ impl Sound {
             samples: None, // default sample size
         };
 
-        let device = audio_subsystem
-            .open_playback(None, &desired_spec, |spec| {
-
-                // initialize the audio callback
-                SquareWave {
-                    phase_inc: 240.0 / spec.freq as f32,
-                    phase: 0.0,
-                    volume: 0.25,
-                }
-            })
-            .unwrap();
-
-        Sound { device }
-    }
+        let queue = audio_subsystem.open_queue(None, &desired_spec).unwrap();
+        queue.resume();
 
-    pub fn start_beep(&self) {
-        self.device.resume();
+        Sound { queue }
     }
 
-    pub fn stop_beep(&self) {
-        self.device.pause();
+    // The rate the CPU should generate samples at.
+    pub fn sample_rate(&self) -> u32 {
+        self.queue.spec().freq as u32
     }
-}
-
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
-}
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
-
-    fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = self.volume * if self.phase < 0.5 { 1.0 } else { -1.0 };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+    pub fn play(&mut self, samples: &[f32]) {
+        let frame = self.sample_rate() / CHIP8_TIMER_HZ;
+        let queued = self.queue.size() / 4;
+        if queued > frame * MAX_QUEUED_FRAMES {
+            self.queue.clear();
+        }
+        // Keep a frame of silence ahead, so that late frames don't leave gaps.
+        if queued == 0 {
+            self.queue.queue(&vec![0.0; frame as usize]);
         }
+        self.queue.queue(samples);
     }
-}
\ No newline at end of file
+}
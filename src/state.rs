@@ -0,0 +1,93 @@
+// Little-endian encoding helpers for the save state format written by
+// `CPU::save_state`.
+
+use std::io::{self, Read, Write};
+
+pub const STATE_MAGIC: [u8; 4] = *b"C8ST";
+// Bump whenever the layout written by `CPU::save_state` changes.
+pub const STATE_VERSION: u16 = 3;
+
+// 64-bit FNV-1a, used to tie save states to the ROM they were taken from.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub struct StateWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> StateWriter<W> {
+
+    pub fn new(inner: W) -> Self {
+        StateWriter { inner }
+    }
+
+    pub fn u8(&mut self, value: u8) -> io::Result<()> {
+        self.inner.write_all(&[value])
+    }
+
+    pub fn bool(&mut self, value: bool) -> io::Result<()> {
+        self.u8(value as u8)
+    }
+
+    pub fn u16(&mut self, value: u16) -> io::Result<()> {
+        self.inner.write_all(&value.to_le_bytes())
+    }
+
+    pub fn u32(&mut self, value: u32) -> io::Result<()> {
+        self.inner.write_all(&value.to_le_bytes())
+    }
+
+    pub fn u64(&mut self, value: u64) -> io::Result<()> {
+        self.inner.write_all(&value.to_le_bytes())
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> io::Result<()> {
+        self.inner.write_all(value)
+    }
+}
+
+pub struct StateReader<R> {
+    inner: R,
+}
+
+impl<R: Read> StateReader<R> {
+
+    pub fn new(inner: R) -> Self {
+        StateReader { inner }
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0; 1];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        let mut buf = [0; 2];
+        self.inner.read_exact(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.inner.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        self.inner.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn bytes(&mut self, value: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(value)
+    }
+}
@@ -0,0 +1,231 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use chip8::cpu::CHIP8_N_KEYS;
+use chip8::image;
+use chip8::Framebuffer;
+
+// Terminals only report key presses, and the auto-repeats of a key held down,
+// never releases. A key counts as held for this many frames after the
+// terminal last sent it: longer than the usual delay before a held key
+// starts repeating (250 to 500 ms), so that it doesn't seem to be released
+// and pressed again.
+const KEY_HOLD_FRAMES: u32 = 30;
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const ESCAPE: u8 = 0x1B;
+const DELETE: u8 = 0x7F;
+
+// A frontend for ANSI terminals, for when no window can be opened (e.g. over
+// SSH). The terminal is put in raw mode for as long as this is alive.
+pub struct Terminal {
+    // `stty` settings to restore when done.
+    saved: String,
+    input: Receiver<u8>,
+    keys: Keys,
+    // Resolution of the last frame drawn, to clear the screen when it changes.
+    resolution: (usize, usize),
+}
+
+impl Terminal {
+
+    pub fn new() -> io::Result<Terminal> {
+        let saved = stty(&["-g"])?;
+        // Raw input, but keep output processing so that messages on stderr
+        // still start on a new line.
+        stty(&["raw", "-echo", "opost"])?;
+
+        // Reads block, so they are done on a thread of their own.
+        let (sender, input) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 64];
+            loop {
+                let count = match io::stdin().read(&mut buffer) {
+                    Ok(0) | Err(_) => return,
+                    Ok(count) => count,
+                };
+                if buffer[..count].iter().any(|&byte| sender.send(byte).is_err()) {
+                    return;
+                }
+            }
+        });
+
+        let mut terminal = Terminal {
+            saved: saved.trim().to_string(),
+            input,
+            keys: Keys::default(),
+            resolution: (0, 0),
+        };
+        // Switch to the alternate screen and hide the cursor.
+        terminal.write(b"\x1b[?1049h\x1b[?25l")?;
+        Ok(terminal)
+    }
+
+    // Reads the keys typed since the last call, once per frame. Fails when
+    // the user quits with Ctrl-C or the input is closed.
+    pub fn poll(&mut self) -> Result<[bool; CHIP8_N_KEYS], ()> {
+        self.keys.next_frame();
+        loop {
+            match self.input.try_recv() {
+                Ok(byte) => self.keys.feed(byte)?,
+                Err(TryRecvError::Disconnected) => return Err(()),
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        Ok(self.keys.keypad())
+    }
+
+    // Fast-forward is active while Tab is held.
+    pub fn turbo(&self) -> bool {
+        self.keys.turbo > self.keys.frame
+    }
+
+    // The game runs backwards while Backspace is held.
+    pub fn rewinding(&self) -> bool {
+        self.keys.rewind > self.keys.frame
+    }
+
+    // Draws the `width` x `height` area of the framebuffer from the top left
+    // corner, one line for every two pixel rows.
+    pub fn draw(&mut self, pixels: &Framebuffer, resolution: (usize, usize)) {
+        let mut screen = Vec::new();
+        if resolution != self.resolution {
+            screen.extend_from_slice(b"\x1b[2J");
+            self.resolution = resolution;
+        }
+        screen.extend_from_slice(b"\x1b[H");
+        let _ = image::write_half_blocks(pixels, resolution, &mut screen);
+        let _ = self.write(&screen);
+    }
+
+    // The buzzer: terminals can only ring their bell.
+    pub fn beep(&mut self) {
+        let _ = self.write(b"\x07");
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(bytes)?;
+        stdout.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.write(b"\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+// Where the input is within an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Escape {
+    #[default]
+    None,
+    // After ESC.
+    Start,
+    // After ESC [ (CSI), up to the final byte.
+    Csi,
+    // After ESC O (SS3), before the final byte.
+    Ss3,
+}
+
+// The keys held, from the bytes typed.
+#[derive(Debug, Default)]
+struct Keys {
+    // Frames counted by `next_frame`.
+    frame: u32,
+    // The frame until which each key counts as held.
+    keypad: [u32; CHIP8_N_KEYS],
+    turbo: u32,
+    rewind: u32,
+    escape: Escape,
+}
+
+impl Keys {
+
+    fn next_frame(&mut self) {
+        self.frame += 1;
+        // A lone ESC is the Escape key, not the start of a sequence that
+        // was split between reads.
+        if self.escape == Escape::Start {
+            self.escape = Escape::None;
+        }
+    }
+
+    // Fails on Ctrl-C. Escape sequences, sent by the arrow, function and
+    // editing keys, and by Alt with a key, are skipped: their last byte is
+    // often a letter of the keypad.
+    fn feed(&mut self, byte: u8) -> Result<(), ()> {
+        let until = self.frame + KEY_HOLD_FRAMES;
+        match (self.escape, byte) {
+            (_, CTRL_C) => return Err(()),
+            (Escape::None, ESCAPE) => self.escape = Escape::Start,
+            (Escape::Start, b'[') => self.escape = Escape::Csi,
+            (Escape::Start, b'O') => self.escape = Escape::Ss3,
+            // Parameters and intermediates continue a CSI sequence.
+            (Escape::Csi, 0x20..=0x3F) => {}
+            (Escape::Start, _) | (Escape::Csi, _) | (Escape::Ss3, _) => self.escape = Escape::None,
+            (Escape::None, b'\t') => self.turbo = until,
+            (Escape::None, BACKSPACE) | (Escape::None, DELETE) => self.rewind = until,
+            (Escape::None, _) => {
+                if let Some(key) = keypad_key(byte) {
+                    self.keypad[key] = until;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn keypad(&self) -> [bool; CHIP8_N_KEYS] {
+        let mut keypad = [false; CHIP8_N_KEYS];
+        for (pressed, &until) in keypad.iter_mut().zip(&self.keypad) {
+            *pressed = until > self.frame;
+        }
+        keypad
+    }
+}
+
+// Runs `stty` on the terminal and returns what it printed.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("standard input is not a terminal"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// The same layout as the SDL frontend: the left side of the keyboard,
+//   1 2 3 4      1 2 3 C
+//   q w e r  ->  4 5 6 D
+//   a s d f      7 8 9 E
+//   z x c v      A 0 B F
+fn keypad_key(byte: u8) -> Option<usize> {
+    match byte.to_ascii_lowercase() {
+        b'1' => Some(0x1),
+        b'2' => Some(0x2),
+        b'3' => Some(0x3),
+        b'4' => Some(0xC),
+        b'q' => Some(0x4),
+        b'w' => Some(0x5),
+        b'e' => Some(0x6),
+        b'r' => Some(0xD),
+        b'a' => Some(0x7),
+        b's' => Some(0x8),
+        b'd' => Some(0x9),
+        b'f' => Some(0xE),
+        b'z' => Some(0xA),
+        b'x' => Some(0x0),
+        b'c' => Some(0xB),
+        b'v' => Some(0xF),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+#[path = "./terminal_tests.rs"]
+mod terminal_tests;
@@ -0,0 +1,39 @@
+use crate::terminal::*;
+
+// Feeds `input` as the bytes typed during one frame, and returns the keypad.
+fn frame(keys: &mut Keys, input: &[u8]) -> [bool; 16] {
+    keys.next_frame();
+    for &byte in input {
+        keys.feed(byte).unwrap();
+    }
+    keys.keypad()
+}
+
+#[test]
+fn held_keys_survive_the_repeat_delay() {
+    let mut keys = Keys::default();
+    assert!(frame(&mut keys, b"w")[0x5]);
+    // 500 ms until the terminal starts repeating the key.
+    for _ in 1..30 {
+        assert!(frame(&mut keys, b"")[0x5]);
+    }
+    assert!(frame(&mut keys, b"w")[0x5]);
+    for _ in 1..30 {
+        assert!(frame(&mut keys, b"")[0x5]);
+    }
+    assert!(!frame(&mut keys, b"")[0x5]);
+}
+
+#[test]
+fn escape_sequences_are_skipped() {
+    let mut keys = Keys::default();
+    // Up, Right, Left, F1, F5, Home, and Alt-q.
+    let keypad = frame(&mut keys, b"\x1b[A\x1b[C\x1b[D\x1bOP\x1b[15~\x1b[1;5H\x1bq");
+    assert_eq!(keypad, [false; 16]);
+    assert!(frame(&mut keys, b"\x1b[Ae")[0x6]);
+
+    // A lone Escape doesn't swallow the next frame's key.
+    frame(&mut keys, b"\x1b");
+    assert!(frame(&mut keys, b"a")[0x7]);
+    assert!(keys.feed(0x03).is_err());
+}
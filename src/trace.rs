@@ -0,0 +1,157 @@
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::cpu::{StepOutcome, CPU};
+use crate::error::CpuError;
+use crate::instruction::{decode, Instruction};
+
+// Execution traces: one line per instruction executed, for comparing runs
+// against each other or against other emulators. Each line holds the cycle
+// (instructions executed since tracing started, from 0), the address and
+// opcode, the mnemonic, the registers the instruction touches and I, all
+// sampled after the instruction ran:
+//
+//       1234 0206 8124  ADD V1, V2            V1=0A V2=05 VF=00         I=0300
+//
+// Frontends run frames through `Tracer::run_frame` instead of
+// `CPU::run_frame`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    // Aligned columns, as above.
+    Text,
+    // cycle,pc,opcode,mnemonic,registers,i
+    Csv,
+}
+
+impl TraceFormat {
+
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" => Some(TraceFormat::Text),
+            "csv" => Some(TraceFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+// Which instructions are traced. All of them by default; an instruction has
+// to pass every filter that is set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<usize>>,
+    // Opcode classes, i.e. the first hex digit of the opcode. Empty for all.
+    pub classes: Vec<u8>,
+    pub cycles: Option<RangeInclusive<u64>>,
+}
+
+impl TraceFilter {
+
+    pub fn matches(&self, cycle: u64, pc: usize, opcode: u16) -> bool {
+        self.addresses.as_ref().is_none_or(|addresses| addresses.contains(&pc))
+            && (self.classes.is_empty() || self.classes.contains(&((opcode >> 12) as u8)))
+            && self.cycles.as_ref().is_none_or(|cycles| cycles.contains(&cycle))
+    }
+}
+
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+    cycle: u64,
+    // The first write error. Tracing stops there, the emulation carries on.
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+
+    pub fn new(writer: W, format: TraceFormat, filter: TraceFilter) -> Tracer<W> {
+        Tracer { writer, format, filter, cycle: 0, error: None }
+    }
+
+    // Instructions executed so far.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    // Executes and traces a single instruction, like `CPU::step`. Steps spent
+    // waiting for a key are neither traced nor counted.
+    pub fn step(&mut self, cpu: &mut CPU) -> Result<StepOutcome, CpuError> {
+        // Fx0A was traced when it started waiting.
+        if cpu.waiting_for_key() {
+            return cpu.step();
+        }
+        let pc = cpu.pc();
+        let memory = cpu.memory();
+        let word = |address: usize| -> Option<u16> {
+            Some((*memory.get(address)? as u16) << 8 | *memory.get(address + 1)? as u16)
+        };
+        let opcode = word(pc);
+        let long_address = word(pc + 2);
+
+        let outcome = cpu.step()?;
+        let opcode = match opcode {
+            Some(opcode) if outcome == StepOutcome::Executed => opcode,
+            _ => return Ok(outcome),
+        };
+        if self.error.is_none() && self.filter.matches(self.cycle, pc, opcode) {
+            let instruction = decode(opcode);
+            let mnemonic = match (instruction, long_address) {
+                (Instruction::LdLong, Some(address)) => format!("LD I, {:#06X}", address),
+                _ => instruction.to_string(),
+            };
+            if let Err(e) = self.write_line(cpu, pc, opcode, &mnemonic, &instruction.registers()) {
+                self.error = Some(e);
+            }
+        }
+        self.cycle += 1;
+        Ok(outcome)
+    }
+
+    // Runs one 60 Hz frame like `CPU::run_frame`, tracing every instruction.
+    pub fn run_frame(&mut self, cpu: &mut CPU, instructions: u32) -> Result<bool, CpuError> {
+        let mut redraw = false;
+        for _ in 0..instructions {
+            let outcome = self.step(cpu)?;
+            redraw |= cpu.redraw();
+            if outcome == StepOutcome::Exited {
+                break;
+            }
+        }
+        cpu.end_frame();
+        Ok(redraw)
+    }
+
+    // Flushes the trace, and returns the first error that occurred writing it.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_line(&mut self, cpu: &CPU, pc: usize, opcode: u16, mnemonic: &str, registers: &[usize]) -> io::Result<()> {
+        let registers: Vec<String> = registers
+            .iter()
+            .map(|&x| format!("V{:X}={:02X}", x, cpu.v()[x]))
+            .collect();
+        let registers = registers.join(" ");
+        match self.format {
+            TraceFormat::Text => writeln!(
+                self.writer,
+                "{:>10} {:04X} {:04X}  {:<20}  {:<24}  I={:04X}",
+                self.cycle, pc, opcode, mnemonic, registers, cpu.i()
+            ),
+            TraceFormat::Csv => writeln!(
+                self.writer,
+                "{},{:04X},{:04X},\"{}\",{},{:04X}",
+                self.cycle, pc, opcode, mnemonic, registers, cpu.i()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "./trace_tests.rs"]
+mod trace_tests;
@@ -0,0 +1,74 @@
+use crate::cpu::CPU;
+use crate::quirks::Quirks;
+use crate::trace::*;
+
+static PROGRAM: [u8; 12] = [
+    0x61, 0x0A, // LD V1, 0x0A
+    0x62, 0x05, // LD V2, 0x05
+    0x81, 0x24, // ADD V1, V2
+    0xA3, 0x00, // LD I, 0x300
+    0xF1, 0x55, // LD [I], V1
+    0x12, 0x0A, // JP 0x20A
+];
+
+fn trace(format: TraceFormat, filter: TraceFilter, instructions: u32) -> Vec<String> {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&PROGRAM).unwrap();
+    let mut tracer = Tracer::new(Vec::new(), format, filter);
+    tracer.run_frame(&mut cpu, instructions).unwrap();
+    assert_eq!(tracer.cycle(), instructions as u64);
+    let trace = String::from_utf8(tracer.finish().unwrap()).unwrap();
+    trace.lines().map(|line| line.trim_end().to_string()).collect()
+}
+
+#[test]
+fn text_trace() {
+    let lines = trace(TraceFormat::Text, TraceFilter::default(), 7);
+    assert_eq!(lines.len(), 7);
+    assert_eq!(lines[2], format!("{:>10} 0204 8124  {:<20}  {:<24}  I=0000", 2, "ADD V1, V2", "V1=0F V2=05 VF=00"));
+    assert_eq!(lines[3], format!("{:>10} 0206 A300  {:<20}  {:<24}  I=0300", 3, "LD I, 0x300", ""));
+    assert!(lines[4].contains("LD [I], V1            V0=00 V1=0F"));
+    assert!(lines[6].starts_with("         6 020A 120A  JP 0x20A"));
+}
+
+#[test]
+fn csv_trace() {
+    let lines = trace(TraceFormat::Csv, TraceFilter::default(), 3);
+    assert_eq!(lines, vec![
+        "0,0200,610A,\"LD V1, 0x0A\",V1=0A,0000",
+        "1,0202,6205,\"LD V2, 0x05\",V2=05,0000",
+        "2,0204,8124,\"ADD V1, V2\",V1=0F V2=05 VF=00,0000",
+    ]);
+}
+
+#[test]
+fn filters() {
+    let filter = TraceFilter { addresses: Some(0x202..=0x206), ..TraceFilter::default() };
+    let lines = trace(TraceFormat::Csv, filter, 10);
+    let pcs: Vec<&str> = lines.iter().map(|line| &line[2..6]).collect();
+    assert_eq!(pcs, vec!["0202", "0204", "0206"]);
+
+    let filter = TraceFilter { classes: vec![0x1, 0xA], ..TraceFilter::default() };
+    let lines = trace(TraceFormat::Csv, filter, 8);
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("3,0206,A300"));
+    assert!(lines[3].starts_with("7,020A,120A"));
+
+    let filter = TraceFilter { cycles: Some(5..=6), classes: vec![0x1], ..TraceFilter::default() };
+    let lines = trace(TraceFormat::Csv, filter, 10);
+    assert_eq!(lines, vec!["5,020A,120A,\"JP 0x20A\",,0300", "6,020A,120A,\"JP 0x20A\",,0300"]);
+}
+
+#[test]
+fn key_wait_is_traced_once() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(&[0xF3, 0x0A, 0x00, 0xE0]).unwrap();
+    let mut tracer = Tracer::new(Vec::new(), TraceFormat::Csv, TraceFilter::default());
+    tracer.run_frame(&mut cpu, 5).unwrap();
+    cpu.set_key(0x4, true);
+    tracer.run_frame(&mut cpu, 1).unwrap();
+    cpu.set_key(0x4, false);
+    tracer.run_frame(&mut cpu, 2).unwrap();
+    let trace = String::from_utf8(tracer.finish().unwrap()).unwrap();
+    assert_eq!(trace, "0,0200,F30A,\"LD V3, K\",V3=00,0000\n1,0202,00E0,\"CLS\",,0000\n");
+}
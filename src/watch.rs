@@ -0,0 +1,127 @@
+use std::fmt;
+
+use crate::instruction::decode;
+
+// Watchpoints: the CPU records a `WatchHit` whenever an instruction accesses
+// watched memory, or changes a watched register. Frontends collect the hits
+// with `CPU::take_watch_hits` after each step; the debugger stops on them.
+
+// The kind of memory access that triggers a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+
+    pub fn from_name(name: &str) -> Option<Access> {
+        match name {
+            "r" | "read" => Some(Access::Read),
+            "w" | "write" => Some(Access::Write),
+            "rw" | "access" => Some(Access::ReadWrite),
+            _ => None,
+        }
+    }
+
+    // Whether a watchpoint for `self` accesses triggers on `access`.
+    pub fn covers(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::ReadWrite => write!(f, "read/write"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Watchpoint {
+    // Bytes `start` to `end`, both inclusive.
+    Memory { start: usize, end: usize, access: Access },
+    // A V register; triggers when its value changes.
+    Register(usize),
+    // I; triggers when its value changes.
+    Index,
+}
+
+impl Watchpoint {
+
+    pub fn memory(address: usize, access: Access) -> Watchpoint {
+        Watchpoint::Memory { start: address, end: address, access }
+    }
+
+    // Whether an `access` to `address` triggers the watchpoint.
+    pub fn covers(&self, address: usize, access: Access) -> bool {
+        match *self {
+            Watchpoint::Memory { start, end, access: watched } => {
+                (start..=end).contains(&address) && watched.covers(access)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Watchpoint::Memory { start, end, access } if start == end => write!(f, "{:#05X} ({})", start, access),
+            Watchpoint::Memory { start, end, access } => write!(f, "{:#05X}-{:#05X} ({})", start, end, access),
+            Watchpoint::Register(x) => write!(f, "V{:X}", x),
+            Watchpoint::Index => write!(f, "I"),
+        }
+    }
+}
+
+// What a watchpoint saw.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Memory(usize),
+    Register(usize),
+    Index,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Memory(address) => write!(f, "{:#05X}", address),
+            Target::Register(x) => write!(f, "V{:X}", x),
+            Target::Index => write!(f, "I"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchHit {
+    // The instruction responsible.
+    pub pc: usize,
+    pub opcode: u16,
+    pub target: Target,
+    // Registers only ever report writes.
+    pub access: Access,
+    // For reads, both are the value read.
+    pub old: usize,
+    pub new: usize,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} of {}", self.access, self.target)?;
+        if self.access == Access::Read {
+            write!(f, ": {:#04X}", self.new)?;
+        } else {
+            write!(f, ": {:#04X} -> {:#04X}", self.old, self.new)?;
+        }
+        write!(f, " by {} at {:#05X}", decode(self.opcode), self.pc)
+    }
+}
+
+#[cfg(test)]
+#[path = "./watch_tests.rs"]
+mod watch_tests;
@@ -0,0 +1,102 @@
+use crate::cpu::CPU;
+use crate::quirks::Quirks;
+use crate::watch::*;
+
+fn run(program: &[u8], watchpoints: &[Watchpoint], instructions: usize) -> (CPU, Vec<WatchHit>) {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(program).unwrap();
+    for &watchpoint in watchpoints {
+        assert!(cpu.add_watchpoint(watchpoint));
+    }
+    let mut hits = Vec::new();
+    for _ in 0..instructions {
+        cpu.step().unwrap();
+        hits.extend(cpu.take_watch_hits());
+    }
+    (cpu, hits)
+}
+
+static BCD: [u8; 8] = [
+    0x60, 0x7B, // LD V0, 123
+    0xA3, 0x00, // LD I, 0x300
+    0xF0, 0x33, // LD B, V0
+    0xF2, 0x65, // LD V0-V2, [I]
+];
+
+#[test]
+fn memory_writes() {
+    let watchpoint = Watchpoint::Memory { start: 0x301, end: 0x302, access: Access::Write };
+    let (_, hits) = run(&BCD, &[watchpoint], 4);
+    assert_eq!(hits, vec![
+        WatchHit { pc: 0x204, opcode: 0xF033, target: Target::Memory(0x301), access: Access::Write, old: 0, new: 2 },
+        WatchHit { pc: 0x204, opcode: 0xF033, target: Target::Memory(0x302), access: Access::Write, old: 0, new: 3 },
+    ]);
+    assert_eq!(hits[0].to_string(), "write of 0x301: 0x00 -> 0x02 by LD B, V0 at 0x204");
+}
+
+#[test]
+fn memory_reads() {
+    let (_, hits) = run(&BCD, &[Watchpoint::memory(0x300, Access::Read)], 4);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].to_string(), "read of 0x300: 0x01 by LD V2, [I] at 0x206");
+
+    let (_, hits) = run(&BCD, &[Watchpoint::memory(0x300, Access::ReadWrite)], 4);
+    let accesses: Vec<Access> = hits.iter().map(|hit| hit.access).collect();
+    assert_eq!(accesses, vec![Access::Write, Access::Read]);
+}
+
+#[test]
+fn sprite_reads_are_watched() {
+    // LD I, 0x206; DRW V0, V0, 1; the sprite byte.
+    let program = [0xA2, 0x06, 0xD0, 0x01, 0x00, 0x00, 0xFF];
+    let (_, hits) = run(&program, &[Watchpoint::memory(0x206, Access::Read)], 2);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].pc, 0x202);
+}
+
+#[test]
+fn fetches_are_not_reads() {
+    let (_, hits) = run(&BCD, &[Watchpoint::Memory { start: 0x200, end: 0x207, access: Access::Read }], 4);
+    assert!(hits.is_empty());
+}
+
+#[test]
+fn registers_and_index() {
+    let (_, hits) = run(&BCD, &[Watchpoint::Register(0x2), Watchpoint::Index], 4);
+    assert_eq!(hits, vec![
+        WatchHit { pc: 0x202, opcode: 0xA300, target: Target::Index, access: Access::Write, old: 0, new: 0x300 },
+        WatchHit { pc: 0x206, opcode: 0xF265, target: Target::Register(2), access: Access::Write, old: 0, new: 3 },
+    ]);
+}
+
+#[test]
+fn registers_past_vf_are_rejected() {
+    let (mut cpu, _) = run(&BCD, &[], 0);
+    assert!(!cpu.add_watchpoint(Watchpoint::Register(16)));
+    assert!(cpu.watchpoints().is_empty());
+    cpu.step().unwrap();
+}
+
+#[test]
+fn key_wait_writes_the_register() {
+    let (mut cpu, _) = run(&[0xF5, 0x0A], &[Watchpoint::Register(0x5)], 1);
+    cpu.set_key(0xC, true);
+    cpu.step().unwrap();
+    cpu.set_key(0xC, false);
+    cpu.step().unwrap();
+    let hits = cpu.take_watch_hits();
+    assert_eq!(hits.len(), 1);
+    assert_eq!((hits[0].pc, hits[0].new), (0x200, 0xC));
+}
+
+#[test]
+fn watchpoints_survive_reset_and_states() {
+    let (mut cpu, _) = run(&BCD, &[Watchpoint::Index], 1);
+    let mut state = Vec::new();
+    cpu.save_state(&mut state).unwrap();
+    cpu.load_state(&state[..]).unwrap();
+    cpu.load_rom(&BCD).unwrap();
+    assert_eq!(cpu.watchpoints(), &[Watchpoint::Index]);
+    assert!(cpu.remove_watchpoint(&Watchpoint::Index));
+    assert!(!cpu.remove_watchpoint(&Watchpoint::Index));
+}